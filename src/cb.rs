@@ -3,7 +3,8 @@ use std::marker::PhantomData;
 use crossbeam_channel::{Receiver, Sender};
 
 use crate::st::{
-    Action, Branch, Choice, End, Message, OfferOne, OfferTwo, Role, SelectOne, SelectTwo,
+    Action, Branch, Choice, End, Message, Nested, OfferOne, OfferTwo, Role, SelectOne, SelectThree,
+    SelectTwo,
 };
 
 macro_rules! cb_message {
@@ -14,11 +15,13 @@ macro_rules! cb_message {
             fn to_net_representation(self) -> NetRepresentation {
                 NetRepresentation::$name(self)
             }
-            fn from_net_representation(net: NetRepresentation) -> Self {
+            fn try_from_net_representation(
+                net: NetRepresentation,
+            ) -> Result<Self, NetRepresentation> {
                 if let NetRepresentation::$name(msg) = net {
-                    msg
+                    Ok(msg)
                 } else {
-                    panic!("Wrong message type")
+                    Err(net)
                 }
             }
         }
@@ -34,13 +37,48 @@ pub enum NetRepresentation {
     Connected(Connected),
     Close(Close),
     Data(Data),
+    Written(Written),
+    Urgent(Urgent),
+    UrgentData(UrgentData),
+    Abort(Abort),
 }
 
 impl NetRepresentation {}
 
 pub trait CrossbeamMessage: Message {
     fn to_net_representation(self) -> NetRepresentation;
-    fn from_net_representation(packet: NetRepresentation) -> Self;
+    fn try_from_net_representation(packet: NetRepresentation) -> Result<Self, NetRepresentation>
+    where
+        Self: Sized;
+
+    fn from_net_representation(packet: NetRepresentation) -> Self
+    where
+        Self: Sized,
+    {
+        Self::try_from_net_representation(packet).unwrap_or_else(|_| panic!("Wrong message type"))
+    }
+}
+
+/// Offers with more than two branches carry one of the messages of the
+/// nested branches, whichever the representation turns out to be.
+impl<M1, M2> CrossbeamMessage for Nested<M1, M2>
+where
+    M1: CrossbeamMessage,
+    M2: CrossbeamMessage,
+{
+    fn to_net_representation(self) -> NetRepresentation {
+        match self {
+            Nested::Left(m1) => m1.to_net_representation(),
+            Nested::Right(m2) => m2.to_net_representation(),
+        }
+    }
+
+    fn try_from_net_representation(packet: NetRepresentation) -> Result<Self, NetRepresentation> {
+        match M1::try_from_net_representation(packet) {
+            Ok(m1) => Ok(Nested::Left(m1)),
+            Err(packet) => M2::try_from_net_representation(packet).map(Nested::Right),
+        }
+    }
 }
 
 cb_message!(Open);
@@ -48,6 +86,13 @@ cb_message!(TcbCreated);
 cb_message!(Connected);
 cb_message!(Close);
 cb_message!(Data, Vec<u8>);
+cb_message!(Written, usize);
+// how many bytes at the front of the following Data are urgent
+cb_message!(Urgent, usize);
+// user data to be sent as urgent
+cb_message!(UrgentData, Vec<u8>);
+// reset the connection instead of closing it
+cb_message!(Abort);
 
 /// [CrossBeamRoleChannel] is a session-typed communication channel that uses crossbeam channels under the hood.
 /// [CrossBeamRoleChannel] behaves as any other session-typed channels and implements [SessionTypedChannel].
@@ -155,6 +200,46 @@ where
         A2::new()
     }
 
+    /// Select the second branch of a select with three branches.
+    pub fn select_right_left<M1, M2, M3, A1, A2, A3>(
+        &mut self,
+        _o: SelectThree<R2, M1, M2, M3, A1, A2, A3>,
+        message: M2,
+    ) -> A2
+    where
+        R1: Role,
+        R2: Role,
+        M1: CrossbeamMessage,
+        M2: CrossbeamMessage,
+        M3: CrossbeamMessage,
+        A1: Action,
+        A2: Action,
+        A3: Action,
+    {
+        self.send.send(message.to_net_representation()).unwrap();
+        A2::new()
+    }
+
+    /// Select the third branch of a select with three branches.
+    pub fn select_right_right<M1, M2, M3, A1, A2, A3>(
+        &mut self,
+        _o: SelectThree<R2, M1, M2, M3, A1, A2, A3>,
+        message: M3,
+    ) -> A3
+    where
+        R1: Role,
+        R2: Role,
+        M1: CrossbeamMessage,
+        M2: CrossbeamMessage,
+        M3: CrossbeamMessage,
+        A1: Action,
+        A2: Action,
+        A3: Action,
+    {
+        self.send.send(message.to_net_representation()).unwrap();
+        A3::new()
+    }
+
     pub fn close(self, _end: End) {
         drop(self);
     }
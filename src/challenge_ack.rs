@@ -0,0 +1,60 @@
+use smoltcp::time::{Duration, Instant};
+use std::sync::Mutex;
+
+/// Bounds how many challenge ACKs go out per second. Shared through
+/// [TcpConfig](crate::tcp::TcpConfig), every connection created from clones
+/// of the same config draws from the same budget, which makes the limit
+/// system-wide.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-7>
+#[derive(Debug)]
+pub struct ChallengeAckLimit {
+    per_second: u32,
+    /// Start of the current one second interval and challenge ACKs sent in it.
+    sent: Mutex<(Instant, u32)>,
+}
+
+impl ChallengeAckLimit {
+    pub fn new(per_second: u32) -> Self {
+        ChallengeAckLimit {
+            per_second,
+            sent: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Take one challenge ACK out of the budget, `false` if it is used up.
+    pub fn allow(&self, now: Instant) -> bool {
+        let mut sent = self.sent.lock().unwrap();
+        let (start, count) = &mut *sent;
+        if now - *start >= Duration::from_secs(1) {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.per_second {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+impl Default for ChallengeAckLimit {
+    /// Same as Linux.
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_refills_every_second() {
+        let limit = ChallengeAckLimit::new(1);
+        let now = Instant::now();
+        assert!(limit.allow(now));
+        assert!(!limit.allow(now + Duration::from_millis(999)));
+        assert!(limit.allow(now + Duration::from_secs(1)));
+    }
+}
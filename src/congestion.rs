@@ -0,0 +1,332 @@
+use log::debug;
+use smoltcp::time::Instant;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// Window management of a congestion control algorithm. Loss detection and
+/// fast recovery are left to the caller, which only reports the events.
+pub trait CongestionControl: Debug + Send {
+    /// `acked` bytes of new data were acknowledged outside of fast recovery.
+    fn on_ack(&mut self, acked: usize, mss: usize);
+
+    /// Duplicate ACKs signalled a loss, fast recovery starts.
+    fn on_loss(&mut self, flight_size: usize, mss: usize);
+
+    /// The retransmission timer fired.
+    fn on_rto(&mut self, flight_size: usize, mss: usize);
+
+    /// Congestion window in bytes.
+    fn cwnd(&self) -> usize;
+
+    /// Slow start threshold in bytes.
+    fn ssthresh(&self) -> usize;
+
+    fn clone_box(&self) -> Box<dyn CongestionControl>;
+}
+
+impl Clone for Box<dyn CongestionControl> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Which [CongestionControl] new connections use.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    Reno,
+    Cubic,
+}
+
+impl CongestionAlgorithm {
+    pub fn build(self, mss: usize) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::Reno => Box::new(Reno::new(mss)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+        }
+    }
+}
+
+impl FromStr for CongestionAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reno" => Ok(CongestionAlgorithm::Reno),
+            "cubic" => Ok(CongestionAlgorithm::Cubic),
+            _ => Err(format!("unknown congestion control algorithm {s:?}")),
+        }
+    }
+}
+
+/// Link: <https://datatracker.ietf.org/doc/html/rfc5681#section-3.1>
+fn initial_window(mss: usize) -> usize {
+    if mss > 2190 {
+        2 * mss
+    } else if mss > 1095 {
+        3 * mss
+    } else {
+        4 * mss
+    }
+}
+
+/// Reno. Fast recovery with the NewReno modification is handled by `Tcp`.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc5681>
+#[derive(Copy, Clone, Debug)]
+pub struct Reno {
+    cwnd: usize,
+    ssthresh: usize,
+    /// Bytes acknowledged since cwnd last grew in congestion avoidance.
+    bytes_acked: usize,
+}
+
+impl Reno {
+    pub fn new(mss: usize) -> Self {
+        Reno {
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+            bytes_acked: 0,
+        }
+    }
+}
+
+impl CongestionControl for Reno {
+    fn on_ack(&mut self, acked: usize, mss: usize) {
+        if self.cwnd < self.ssthresh {
+            // slow start
+            self.cwnd += acked.min(mss);
+        } else {
+            // congestion avoidance, one MSS per RTT
+            self.bytes_acked += acked;
+            if self.bytes_acked >= self.cwnd {
+                self.bytes_acked -= self.cwnd;
+                self.cwnd += mss;
+            }
+        }
+    }
+
+    fn on_loss(&mut self, flight_size: usize, mss: usize) {
+        self.ssthresh = (flight_size / 2).max(2 * mss);
+        self.cwnd = self.ssthresh;
+        self.bytes_acked = 0;
+    }
+
+    fn on_rto(&mut self, flight_size: usize, mss: usize) {
+        self.ssthresh = (flight_size / 2).max(2 * mss);
+        self.cwnd = mss;
+        self.bytes_acked = 0;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    fn clone_box(&self) -> Box<dyn CongestionControl> {
+        Box::new(*self)
+    }
+}
+
+/// CUBIC. Windows are tracked in bytes but the cubic function works in
+/// segments, as in the RFC.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc9438>
+#[derive(Copy, Clone, Debug)]
+pub struct Cubic {
+    /// Fractional, growth in congestion avoidance comes in small steps.
+    cwnd: f64,
+    ssthresh: usize,
+    /// W_max, the window right before the last reduction, in segments.
+    w_max: f64,
+    /// The Reno-friendly window estimate W_est, in segments.
+    w_est: f64,
+    /// Time it takes the window to grow back to W_max, in seconds.
+    k: f64,
+    /// Start of the current congestion avoidance stage.
+    epoch_start: Option<Instant>,
+}
+
+impl Cubic {
+    const C: f64 = 0.4;
+    const BETA: f64 = 0.7;
+    /// Additive increase giving the same average window as Reno.
+    const ALPHA: f64 = 3.0 * (1.0 - Self::BETA) / (1.0 + Self::BETA);
+
+    pub fn new(mss: usize) -> Self {
+        Cubic {
+            cwnd: initial_window(mss) as f64,
+            ssthresh: usize::MAX,
+            w_max: 0.0,
+            w_est: 0.0,
+            k: 0.0,
+            epoch_start: None,
+        }
+    }
+
+    /// Remember the window we are backing off from, with fast convergence.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9438#section-4.7>
+    fn reduce(&mut self, mss: usize) {
+        let cwnd = self.cwnd / mss as f64;
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + Self::BETA) / 2.0
+        } else {
+            cwnd
+        };
+        self.ssthresh = ((self.cwnd * Self::BETA) as usize).max(2 * mss);
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn on_ack(&mut self, acked: usize, mss: usize) {
+        if self.cwnd() < self.ssthresh {
+            self.cwnd += acked.min(mss) as f64;
+            return;
+        }
+
+        let now = Instant::now();
+        let cwnd = self.cwnd / mss as f64;
+        let epoch_start = match self.epoch_start {
+            Some(epoch_start) => epoch_start,
+            None => {
+                // Link: <https://datatracker.ietf.org/doc/html/rfc9438#section-4.2>
+                self.k = if cwnd < self.w_max {
+                    ((self.w_max - cwnd) / Self::C).cbrt()
+                } else {
+                    self.w_max = cwnd;
+                    0.0
+                };
+                self.w_est = cwnd;
+                self.epoch_start = Some(now);
+                now
+            }
+        };
+
+        let t = (now - epoch_start).total_millis() as f64 / 1000.0;
+        let w_cubic = Self::C * (t - self.k).powi(3) + self.w_max;
+        self.w_est += Self::ALPHA * acked as f64 / self.cwnd;
+
+        let target = if w_cubic < self.w_est {
+            // Reno-friendly region
+            self.w_est
+        } else {
+            w_cubic.clamp(cwnd, 1.5 * cwnd)
+        };
+        self.cwnd += (target - cwnd) / cwnd * acked as f64;
+    }
+
+    fn on_loss(&mut self, _flight_size: usize, mss: usize) {
+        self.reduce(mss);
+        self.cwnd = self.ssthresh as f64;
+        debug!("cubic: W_max {:.1} segments", self.w_max);
+    }
+
+    fn on_rto(&mut self, _flight_size: usize, mss: usize) {
+        self.reduce(mss);
+        self.cwnd = mss as f64;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd as usize
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    fn clone_box(&self) -> Box<dyn CongestionControl> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    #[test]
+    fn initial_window_depends_on_mss() {
+        assert_eq!(initial_window(1000), 4000);
+        assert_eq!(initial_window(1095), 4380);
+        assert_eq!(initial_window(1096), 3288);
+        assert_eq!(initial_window(2190), 6570);
+        assert_eq!(initial_window(2191), 4382);
+    }
+
+    #[test]
+    fn algorithms_are_parsed_by_name() {
+        assert!(matches!("reno".parse(), Ok(CongestionAlgorithm::Reno)));
+        assert!(matches!("cubic".parse(), Ok(CongestionAlgorithm::Cubic)));
+        assert!("vegas".parse::<CongestionAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn reno_windows() {
+        let mut reno = Reno::new(MSS);
+        assert_eq!((reno.cwnd(), reno.ssthresh()), (4000, usize::MAX));
+
+        // slow start grows by at most one MSS per ACK
+        reno.on_ack(1500, MSS);
+        assert_eq!(reno.cwnd(), 5000);
+        reno.on_ack(500, MSS);
+        assert_eq!(reno.cwnd(), 5500);
+
+        reno.on_loss(10000, MSS);
+        assert_eq!((reno.cwnd(), reno.ssthresh()), (5000, 5000));
+
+        // congestion avoidance grows by one MSS per window of ACKed bytes
+        for _ in 0..4 {
+            reno.on_ack(1000, MSS);
+        }
+        assert_eq!(reno.cwnd(), 5000);
+        reno.on_ack(1000, MSS);
+        assert_eq!(reno.cwnd(), 6000);
+
+        // ssthresh never drops below two segments
+        reno.on_rto(3000, MSS);
+        assert_eq!((reno.cwnd(), reno.ssthresh()), (1000, 2000));
+        reno.on_ack(1000, MSS);
+        assert_eq!(reno.cwnd(), 2000);
+    }
+
+    #[test]
+    fn cubic_backs_off_and_converges() {
+        let mut cubic = Cubic::new(MSS);
+        cubic.cwnd = 10000.0;
+        cubic.on_loss(10000, MSS);
+        assert_eq!((cubic.w_max, cubic.ssthresh), (10.0, 7000));
+        assert_eq!(cubic.cwnd(), 7000);
+
+        // the first ACK in congestion avoidance starts the epoch
+        cubic.on_ack(1000, MSS);
+        assert!((cubic.k - ((10.0 - 7.0) / Cubic::C).cbrt()).abs() < 1e-12);
+
+        // losing again below W_max releases bandwidth faster
+        cubic.cwnd = 8000.0;
+        cubic.on_loss(8000, MSS);
+        assert_eq!((cubic.w_max, cubic.ssthresh), (6.8, 5600));
+
+        // 5600 * 0.7 is just below 3920 in floating point
+        cubic.on_rto(5600, MSS);
+        assert_eq!((cubic.cwnd(), cubic.ssthresh), (1000, 3919));
+        assert!((cubic.w_max - 5.6 * 0.85).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cubic_is_reno_friendly() {
+        // right after the epoch starts the cubic function is flat, so the
+        // window follows the Reno estimate
+        let mut cubic = Cubic::new(MSS);
+        cubic.ssthresh = 4000;
+        cubic.on_ack(1000, MSS);
+        assert_eq!(cubic.k, 0.0);
+        assert!((cubic.w_est - (4.0 + Cubic::ALPHA / 4.0)).abs() < 1e-12);
+        assert_eq!(cubic.cwnd(), 4033);
+    }
+}
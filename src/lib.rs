@@ -1,15 +1,22 @@
 pub mod cb;
+pub mod challenge_ack;
+pub mod congestion;
+pub mod iss;
+pub mod reassembly;
+pub mod retransmit_queue;
+pub mod scoreboard;
 pub mod smol_channel;
 pub mod smol_lower;
 pub mod st;
 pub mod st_macros;
 pub mod tcp;
+pub mod timestamps;
 
 use paste::paste;
 use std::marker::PhantomData;
 
-use crate::cb::{Close, Connected, Data, Open, TcbCreated};
-use crate::smol_channel::{Ack, FinAck, Rst, Syn, SynAck};
+use crate::cb::{Abort, Close, Connected, Data, Open, TcbCreated, Urgent, UrgentData, Written};
+use crate::smol_channel::{Ack, FinAck, Rst, Segments, Syn, SynAck};
 use crate::st::{
     Action, End, NestRole, Nested, OfferOne, OfferTwo, Role, SelectOne, SelectTwo, Timeout,
 };
@@ -18,65 +25,295 @@ use crate::st_macros::{Nest, Rec, Role, St};
 Role!(pub RoleServerSystem);
 Role!(pub RoleServerUser);
 Role!(pub RoleClientSystem);
+Role!(pub RoleClientUser);
 
-pub type ServerSystemFinWait1 = St![
-    (RoleClientSystem & {
-        Ack. // ACK of FIN. TODO we should handle other ACKs here as well maybe
-            ServerSystemFinWait2,
-        FinAck. // FIN and ACK of our FIN at the same time
-            (RoleClientSystem + Ack).
-            end
-    }
-)];
+/// The states from the close onwards, the same on both ends of the
+/// connection. `$side` prefixes the names, `$peer` is the system at the
+/// other end and `$user` our own user.
+macro_rules! closing_states {
+    ($side:ident, $peer:ident, $user:ident) => {
+        paste! {
+            Rec!(pub [<$side FinWait1>], [
+                ($peer & {
+                    Ack. // ACK of FIN, possibly with data we don't care about
+                        ($peer + Segments /* ACK of the data, if any */).
+                        [<$side FinWait2>],
+                    FinAck. // FIN and ACK of our FIN at the same time
+                        ($peer + Ack).
+                        [<$side TimeWait>],
+                    FinAck. // simultaneous close, FIN without ACK of our FIN
+                        ($peer + Ack).
+                        [<$side Closing>],
+                    FinAck. // unacceptable or out of order
+                        ($peer + Segments /* ACK, none if rate limited */).
+                        [<$side FinWait1>],
+                    Ack. // ACK of earlier data, possibly with data we don't care about
+                        ($peer + Segments /* ACK of the data if any, and more of ours */).
+                        [<$side FinWait1>],
+                    Timeout. // retransmission of data or the FIN
+                        ($peer + {
+                            Ack.[<$side FinWait1>],
+                            FinAck.[<$side FinWait1>]
+                        }),
+                    Timeout. // send buffer drained, the FIN goes out
+                        ($peer + FinAck).
+                        [<$side FinWait1>],
+                    Timeout. // persist timer
+                        ($peer + Ack /* zero window probe */).
+                        [<$side FinWait1>],
+                    Syn. // the peer may have restarted
+                        ($peer + Segments /* challenge ACK, none if rate limited */).
+                        [<$side FinWait1>],
+                    Rst. // the connection is reset
+                        end,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side FinWait1>]
+                })
+            ]);
 
-Rec!(pub ServerSystemFinWait2, [
-    (RoleClientSystem & {
-        Ack. // data we don't care about
-            (RoleClientSystem + Ack).
-            ServerSystemFinWait2,
-        FinAck. // other peer is closing as well
-            (RoleClientSystem + Ack).
-            end
-    })
-]);
+            Rec!(pub [<$side Closing>], [
+                ($peer & {
+                    Ack. // ACK of our FIN, possibly with data we don't care about
+                        ($peer + Segments /* ACK of the data, if any */).
+                        [<$side TimeWait>],
+                    FinAck. // retransmitted FIN
+                        ($peer + Ack).
+                        [<$side Closing>],
+                    Ack. // ACK of earlier data, or unacceptable
+                        ($peer + Segments /* ACK if any, and more of ours the windows have room for */).
+                        [<$side Closing>],
+                    Timeout. // retransmission of data or the FIN
+                        ($peer + {
+                            Ack.[<$side Closing>],
+                            FinAck.[<$side Closing>]
+                        }),
+                    Timeout. // send buffer drained, the FIN goes out
+                        ($peer + FinAck).
+                        [<$side Closing>],
+                    Timeout. // persist timer
+                        ($peer + Ack /* zero window probe */).
+                        [<$side Closing>],
+                    Syn. // the peer may have restarted
+                        ($peer + Segments /* challenge ACK, none if rate limited */).
+                        [<$side Closing>],
+                    Rst. // the connection is reset
+                        end,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side Closing>]
+                })
+            ]);
+
+            Rec!(pub [<$side FinWait2>], [
+                ($peer & {
+                    Ack. // data we don't care about
+                        ($peer + Segments /* ACK of the data, if any */).
+                        [<$side FinWait2>],
+                    FinAck. // other peer is closing as well
+                        ($peer + Ack).
+                        [<$side TimeWait>],
+                    FinAck. // unacceptable or out of order
+                        ($peer + Segments /* ACK, none if rate limited */).
+                        [<$side FinWait2>],
+                    Syn. // the peer may have restarted
+                        ($peer + Segments /* challenge ACK, none if rate limited */).
+                        [<$side FinWait2>],
+                    Rst. // the connection is reset
+                        end,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side FinWait2>]
+                })
+            ]);
+
+            Rec!(pub [<$side TimeWait>], [
+                ($peer & {
+                    FinAck. // retransmitted FIN, our ACK was lost
+                        ($peer + Ack).
+                        [<$side TimeWait>],
+                    Timeout. // 2*MSL elapsed
+                        end,
+                    Rst. // the connection is reset
+                        end,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side TimeWait>]
+                })
+            ]);
+
+            Rec!(pub [<$side CloseWaitDrain>], [
+                ($peer & {
+                    Ack. // ACK of our data
+                        ($peer + Segments).
+                        [<$side CloseWaitDrain>],
+                    FinAck. // retransmitted FIN, our ACK was lost
+                        ($peer + Ack).
+                        [<$side CloseWaitDrain>],
+                    Timeout. // retransmission
+                        ($peer + Segments).
+                        [<$side CloseWaitDrain>],
+                    Timeout. // persist timer
+                        ($peer + Ack /* zero window probe */).
+                        [<$side CloseWaitDrain>],
+                    Timeout. // everything sent and acknowledged
+                        [<$side CloseWait>],
+                    Syn. // the peer may have restarted
+                        ($peer + Segments /* challenge ACK, none if rate limited */).
+                        [<$side CloseWaitDrain>],
+                    Rst. // the connection is reset
+                        [<$side PeerGone>] /* our user is told when it sends more data */,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side CloseWaitDrain>]
+                })
+            ]);
+
+            Rec!(pub [<$side CloseWait>], [
+                ($user & {
+                    Data.
+                        ($user + Written).
+                        ($peer + Segments).
+                        [<$side CloseWaitDrain>],
+                    Close.
+                        ($peer + FinAck).
+                        [<$side LastAck>],
+                    Abort.
+                        ($peer + Rst).
+                        end
+                })
+            ]);
 
-Rec!(pub ServerSystemCloseWait, [
-    (RoleServerUser & {
+            Rec!(pub [<$side LastAck>], [
+                ($peer & {
+                    Ack. // ACK of our FIN
+                        end,
+                    FinAck. // retransmitted FIN, our ACK was lost
+                        ($peer + Ack).
+                        [<$side LastAck>],
+                    Ack. // anything else
+                        ($peer + Segments /* ACK, if any */).
+                        [<$side LastAck>],
+                    Timeout. // retransmission
+                        ($peer + FinAck).
+                        [<$side LastAck>],
+                    Syn. // the peer may have restarted
+                        ($peer + Segments /* challenge ACK, none if rate limited */).
+                        [<$side LastAck>],
+                    Rst. // the connection is reset
+                        end,
+                    Rst. // not exactly at RCV.NXT
+                        ($peer + Segments /* challenge ACK, none if out of window or rate limited */).
+                        [<$side LastAck>]
+                })
+            ]);
+
+            Rec!(pub [<$side PeerGone>], [
+                ($user & {
+                    Data. // discarded
+                        ($user + Close).
+                        end,
+                    Close.
+                        end,
+                    Abort. // nothing left to reset
+                        end
+                })
+            ]);
+        }
+    };
+}
+
+closing_states!(ServerSystem, RoleClientSystem, RoleServerUser);
+closing_states!(ClientSystem, RoleServerSystem, RoleClientUser);
+
+Rec!(pub ServerSystemDeliver, [
+    (RoleServerUser + {
+        Urgent. // the peer moved its urgent pointer
+            ServerSystemDeliver,
         Data.
-            (RoleClientSystem + Ack).
-            (RoleClientSystem & Ack /* empty ack */).
-            ServerSystemCloseWait,
-        Close.
-            (RoleClientSystem + FinAck).
-            (RoleClientSystem & Ack).
-            end
+            (RoleServerUser & {
+                Data.
+                    (RoleServerUser + Written).
+                    (RoleClientSystem + Segments /* data or window update */).
+                    ServerSystemCommLoop,
+                UrgentData.
+                    (RoleServerUser + Written).
+                    (RoleClientSystem + Segments).
+                    ServerSystemCommLoop,
+                Close.
+                    (RoleClientSystem + Segments /* what the windows have room for, the FIN follows */).
+                    ServerSystemFinWait1,
+                Abort.
+                    (RoleClientSystem + Rst).
+                    end
+            })
     })
 ]);
 
-Rec!(pub ServerSystemCommLoop, [
-    (RoleClientSystem & {
-        Ack. // acceptable with payload
-            (RoleClientSystem + Ack /* empty */).
-            (RoleServerUser + Data).
+Rec!(pub ServerSystemDeliverLast, [
+    (RoleServerUser + {
+        Urgent. // the peer moved its urgent pointer
+            ServerSystemDeliverLast,
+        Data. // what came with the peer's FIN
             (RoleServerUser & {
                 Data.
-                    (RoleClientSystem + Ack /* with data */).
-                    ServerSystemCommLoop,
+                    (RoleServerUser + Written).
+                    (RoleClientSystem + Segments).
+                    (RoleServerUser + Close).
+                    ServerSystemCloseWaitDrain,
+                UrgentData.
+                    (RoleServerUser + Written).
+                    (RoleClientSystem + Segments).
+                    (RoleServerUser + Close).
+                    ServerSystemCloseWaitDrain,
                 Close.
                     (RoleClientSystem + FinAck).
-                    ServerSystemFinWait1
+                    ServerSystemLastAck,
+                Abort.
+                    (RoleClientSystem + Rst).
+                    end
             }),
-        Ack. // acceptable empty
+        Close. // nothing came with the peer's FIN
+            ServerSystemCloseWait
+    })
+]);
+
+Rec!(pub ServerSystemCommLoop, [
+    (RoleClientSystem & {
+        Ack. // acceptable with payload
+            (RoleClientSystem + Ack).
+            ServerSystemDeliver,
+        Ack. // acceptable with payload, the ACK is delayed
+            ServerSystemDeliver,
+        Ack. // acceptable empty, may have opened the window
+            (RoleClientSystem + Segments).
             ServerSystemCommLoop,
         FinAck.
             (RoleClientSystem + Ack /* we ACK the FIN */).
-            (RoleServerUser + Close).
-            ServerSystemCloseWait,
-        Ack. // unacceptable
-            (RoleClientSystem + Ack /* challenge */).
+            ServerSystemDeliverLast,
+        FinAck. // unacceptable or out of order
+            (RoleClientSystem + Segments /* ACK, none if rate limited */).
+            ServerSystemCommLoop,
+        Ack. // unacceptable or out of order
+            (RoleClientSystem + Segments /* challenge or duplicate ACK, none if rate limited */).
             ServerSystemCommLoop,
         Timeout.
-            (RoleClientSystem + Ack /* retransmission */).
+            (RoleClientSystem + Segments /* retransmission or delayed ACK */).
+            ServerSystemCommLoop,
+        Timeout. // persist or keepalive timer
+            (RoleClientSystem + Ack /* zero window or keepalive probe */).
+            ServerSystemCommLoop,
+        Timeout. // keepalive probes unanswered, the peer is gone
+            (RoleServerUser + Close).
+            ServerSystemPeerGone,
+        Syn. // the peer may have restarted
+            (RoleClientSystem + Segments /* challenge ACK, none if rate limited */).
+            ServerSystemCommLoop,
+        Rst. // the connection is reset
+            (RoleServerUser + Close).
+            ServerSystemPeerGone,
+        Rst. // not exactly at RCV.NXT
+            (RoleClientSystem + Segments /* challenge ACK, none if out of window or rate limited */).
             ServerSystemCommLoop
     })
 ]);
@@ -86,27 +323,52 @@ Rec!(pub ServerSystemSynRcvd, [
         Ack. // acceptable
             (RoleServerUser + Connected).
             ServerSystemCommLoop,
+        Ack. // acknowledges something other than our SYN-ACK
+            (RoleClientSystem + Rst).
+            (RoleServerUser + Close).
+            end,
         Ack. // unacceptable
-            (RoleClientSystem + {
-                Ack.ServerSystemSynRcvd,
-                Rst.(RoleServerUser + Close).end
-            })
+            (RoleClientSystem + Segments /* ACK, none if rate limited */).
+            ServerSystemSynRcvd,
+        Timeout.
+            (RoleClientSystem + SynAck /* retransmission */).
+            ServerSystemSynRcvd,
+        Syn. // retransmitted, our SYN-ACK was lost
+            (RoleClientSystem + SynAck).
+            ServerSystemSynRcvd,
+        Syn. // any other
+            (RoleClientSystem + Segments /* challenge ACK, none if rate limited */).
+            ServerSystemSynRcvd,
+        Rst. // the handshake is reset, our user need not know
+            ServerSystemListen,
+        Rst. // not exactly at RCV.NXT
+            (RoleClientSystem + Segments /* challenge ACK, none if out of window or rate limited */).
+            ServerSystemSynRcvd
     })
+]);
 
+Rec!(pub ServerSystemListen, [
+    (RoleClientSystem & Syn).
+    (RoleClientSystem + SynAck).
+    ServerSystemSynRcvd
 ]);
 
 pub type ServerSystemSessionType = St![
     (RoleServerUser & Open).
     (RoleServerUser + TcbCreated).
-    (RoleClientSystem & Syn).
-    (RoleClientSystem + SynAck).
-    ServerSystemSynRcvd
+    ServerSystemListen
 ];
 
 Rec!(pub ServerUserCloseWait, [
     (RoleServerSystem + {
-        Data.ServerUserCloseWait,
-        Close.end
+        Data.
+            (RoleServerSystem & {
+                Written.ServerUserCloseWait,
+                Close. // the connection was reset or the peer is gone
+                    end
+            }),
+        Close.end,
+        Abort.end
     })
 ]);
 
@@ -114,9 +376,13 @@ Rec!(pub ServerUserCommLoop, [
     (RoleServerSystem & {
         Data.
             (RoleServerSystem + {
-                Data.ServerUserCommLoop,
-                Close.end
+                Data.(RoleServerSystem & Written).ServerUserCommLoop,
+                UrgentData.(RoleServerSystem & Written).ServerUserCommLoop,
+                Close.end,
+                Abort.end
             }),
+        Urgent. // the next Data starts with urgent bytes
+            ServerUserCommLoop,
         Close.ServerUserCloseWait
     })
 ]);
@@ -129,3 +395,139 @@ pub type ServerUserSessionType = St![
         Close.end
     })
 ];
+
+Rec!(pub ClientSystemAwaitResponse, [
+    (RoleServerSystem & {
+        Ack. // acceptable with payload
+            (RoleServerSystem + Ack).
+            ClientSystemDeliver,
+        Ack. // acceptable with payload, the ACK is delayed
+            ClientSystemDeliver,
+        Ack. // acceptable empty, may have opened the window
+            (RoleServerSystem + Segments).
+            ClientSystemAwaitResponse,
+        FinAck.
+            (RoleServerSystem + Ack /* we ACK the FIN */).
+            (RoleClientUser + Close).
+            ClientSystemCloseWait,
+        FinAck. // unacceptable or out of order
+            (RoleServerSystem + Segments /* ACK, none if rate limited */).
+            ClientSystemAwaitResponse,
+        Ack. // unacceptable or out of order
+            (RoleServerSystem + Segments /* challenge or duplicate ACK, none if rate limited */).
+            ClientSystemAwaitResponse,
+        Timeout.
+            (RoleServerSystem + Segments /* retransmission or delayed ACK */).
+            ClientSystemAwaitResponse,
+        Timeout. // persist or keepalive timer
+            (RoleServerSystem + Ack /* zero window or keepalive probe */).
+            ClientSystemAwaitResponse,
+        Timeout. // keepalive probes unanswered, the peer is gone
+            (RoleClientUser + Close).
+            ClientSystemPeerGone,
+        Syn. // the peer may have restarted
+            (RoleServerSystem + Segments /* challenge ACK, none if rate limited */).
+            ClientSystemAwaitResponse,
+        Rst. // the connection is reset
+            (RoleClientUser + Close).
+            ClientSystemPeerGone,
+        Rst. // not exactly at RCV.NXT
+            (RoleServerSystem + Segments /* challenge ACK, none if out of window or rate limited */).
+            ClientSystemAwaitResponse
+    })
+]);
+
+Rec!(pub ClientSystemDeliver, [
+    (RoleClientUser + {
+        Urgent. // the peer moved its urgent pointer
+            ClientSystemDeliver,
+        Data.
+            ClientSystemCommLoop
+    })
+]);
+
+Rec!(pub ClientSystemCommLoop, [
+    (RoleClientUser & {
+        Data.
+            (RoleClientUser + Written).
+            (RoleServerSystem + Segments).
+            ClientSystemAwaitResponse,
+        UrgentData.
+            (RoleClientUser + Written).
+            (RoleServerSystem + Segments).
+            ClientSystemAwaitResponse,
+        Close.
+            (RoleServerSystem + Segments /* what the windows have room for, the FIN follows */).
+            ClientSystemFinWait1,
+        Abort.
+            (RoleServerSystem + Rst).
+            end
+    })
+]);
+
+Rec!(pub ClientSystemSynSent, [
+    (RoleServerSystem & {
+        SynAck. // acceptable
+            (RoleServerSystem + Ack).
+            (RoleClientUser + Connected).
+            ClientSystemCommLoop,
+        SynAck. // acknowledges something other than our SYN
+            (RoleServerSystem + Rst).
+            ClientSystemSynSent,
+        Timeout.
+            (RoleServerSystem + Syn /* retransmission */).
+            ClientSystemSynSent,
+        Rst. // acknowledges our SYN, the connection is refused
+            (RoleClientUser + Close).
+            end,
+        Rst. // any other, dropped
+            ClientSystemSynSent
+    })
+]);
+
+pub type ClientSystemSessionType = St![
+    (RoleClientUser & Open).
+    (RoleClientUser + TcbCreated).
+    (RoleServerSystem + Syn).
+    ClientSystemSynSent
+];
+
+Rec!(pub ClientUserCloseWait, [
+    (RoleClientSystem + {
+        Data.
+            (RoleClientSystem & {
+                Written.ClientUserCloseWait,
+                Close. // the connection was reset or the peer is gone
+                    end
+            }),
+        Close.end,
+        Abort.end
+    })
+]);
+
+Rec!(pub ClientUserAwaitResponse, [
+    (RoleClientSystem & {
+        Data.ClientUserCommLoop,
+        Urgent. // the next Data starts with urgent bytes
+            ClientUserAwaitResponse,
+        Close.ClientUserCloseWait
+    })
+]);
+
+Rec!(pub ClientUserCommLoop, [
+    (RoleClientSystem + {
+        Data.(RoleClientSystem & Written).ClientUserAwaitResponse,
+        UrgentData.(RoleClientSystem & Written).ClientUserAwaitResponse,
+        Close.end,
+        Abort.end
+    })
+]);
+
+pub type ClientUserSessionType = St![
+    (RoleClientSystem + Open).
+    (RoleClientSystem & TcbCreated).
+    (RoleClientSystem & {
+        Connected.ClientUserCommLoop,
+        Close.end
+    })
+];
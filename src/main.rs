@@ -1,4 +1,5 @@
 use std::net::Ipv4Addr;
+use std::ops::ControlFlow;
 use std::thread;
 
 use anyhow::Result;
@@ -7,14 +8,25 @@ use log::{info, warn};
 
 use smoltcp::time::Duration;
 use tcpst2::cb::{
-    Close, Connected, CrossBeamRoleChannel, Data, NetRepresentation, Open, TcbCreated,
+    Abort, Close, Connected, CrossBeamRoleChannel, Data, NetRepresentation, Open, TcbCreated,
+    Urgent, UrgentData, Written,
 };
-use tcpst2::smol_channel::SmolChannel;
+use tcpst2::congestion::CongestionAlgorithm;
+use tcpst2::smol_channel::{Rst, Segments, SmolChannel, Syn};
 use tcpst2::smol_lower::SmolLower;
-use tcpst2::st::{nested_offer_two, Action, Branch, Choice, Nested, Timeout};
-use tcpst2::tcp::{LocalAddr, Reaction, ReactionInner, TcpClosed};
+use tcpst2::st::{
+    nested_offer_two, nested_select_left, nested_select_right, Action, Branch, Choice, End, Nested,
+    Timeout,
+};
+use tcpst2::tcp::tcp_state::{
+    CloseWait, Closing, Established, FinWait1, FinWait2, LastAck, TcpState, TimeWait,
+};
+use tcpst2::tcp::{Keepalive, LocalAddr, Reaction, Retransmission, Tcp, TcpClosed, TcpConfig};
 use tcpst2::{
-    RoleClientSystem, RoleServerSystem, RoleServerUser, ServerSystemSessionType,
+    RoleClientSystem, RoleServerSystem, RoleServerUser, ServerSystemCloseWait,
+    ServerSystemCloseWaitDrain, ServerSystemClosing, ServerSystemCommLoop, ServerSystemDeliver,
+    ServerSystemDeliverLast, ServerSystemFinWait1, ServerSystemFinWait2, ServerSystemLastAck,
+    ServerSystemListen, ServerSystemPeerGone, ServerSystemSessionType, ServerSystemTimeWait,
     ServerUserSessionType,
 };
 
@@ -23,6 +35,34 @@ use tcpst2::{
 struct CmdlineArgs {
     #[argh(positional)]
     local_addr: Ipv4Addr,
+
+    /// maximum segment lifetime in seconds, TIME-WAIT lasts twice as long
+    #[argh(option)]
+    msl: Option<u64>,
+
+    /// congestion control algorithm, reno or cubic
+    #[argh(option, default = "CongestionAlgorithm::Reno")]
+    cc: CongestionAlgorithm,
+
+    /// delay ACKs of received data by up to this many milliseconds
+    #[argh(option)]
+    delayed_ack: Option<u64>,
+
+    /// disable Nagle's algorithm
+    #[argh(switch)]
+    nodelay: bool,
+
+    /// probe the peer after the connection is idle for this many seconds
+    #[argh(option)]
+    keepalive_idle: Option<u64>,
+
+    /// seconds between unanswered keepalive probes
+    #[argh(option)]
+    keepalive_interval: Option<u64>,
+
+    /// unanswered keepalive probes before giving up on the peer
+    #[argh(option)]
+    keepalive_probes: Option<u32>,
 }
 
 macro_rules! not_in_st {
@@ -34,6 +74,903 @@ macro_rules! not_in_st {
     }
 }
 
+/// The peer reset the connection.
+fn reset<T>(tcp: Tcp<T>, rst: &Rst)
+where
+    T: TcpState + Clone + 'static,
+{
+    match tcp.recv_rst(rst) {
+        Reaction::Reset(_) => warn!("connection reset by peer"),
+        _ => unreachable!("RST at RCV.NXT must reset"),
+    }
+}
+
+/// A RST that is not exactly at RCV.NXT, answered with a challenge ACK if it
+/// is in the window.
+fn challenge<T>(tcp: Tcp<T>, rst: &Rst) -> (Tcp<T>, Segments)
+where
+    T: TcpState + Clone + 'static,
+{
+    warn!("RST not at RCV.NXT");
+    match tcp.recv_rst(rst) {
+        Reaction::NotAcceptable(tcp, challenge) => (tcp, Segments(challenge.into_iter().collect())),
+        _ => unreachable!("only a RST at RCV.NXT resets"),
+    }
+}
+
+/// A SYN once the connection is synchronized, answered with a challenge ACK.
+fn challenge_syn<T>(tcp: &mut Tcp<T>, syn: &Syn) -> Segments
+where
+    T: TcpState + Clone + 'static,
+{
+    warn!("SYN in a synchronized state");
+    Segments(tcp.recv_syn(syn).into_iter().collect())
+}
+
+/// Hand received data to our user and act on what it does in response.
+/// Breaks once the connection is over.
+fn deliver(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    system_user_channel: &mut CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>,
+    mut tcp: Tcp<Established>,
+    deliver: ServerSystemDeliver,
+) -> ControlFlow<End, (Tcp<Established>, ServerSystemCommLoop)> {
+    let urgent = tcp.urgent();
+    let data = tcp.read();
+    info!("Got {:?} bytes", data.len());
+
+    let st = match urgent {
+        Some(urgent) => {
+            info!("{} of them urgent", urgent);
+            let deliver = system_user_channel.select_left(deliver.inner(), Urgent(urgent));
+            system_user_channel.select_right(deliver.inner(), Data(data))
+        }
+        None => system_user_channel.select_right(deliver.inner(), Data(data)),
+    };
+
+    match system_user_channel.offer_two(st, |net| match net {
+        NetRepresentation::Data(_) => Choice::Left,
+        NetRepresentation::UrgentData(_)
+        | NetRepresentation::Close(_)
+        | NetRepresentation::Abort(_) => Choice::Right,
+        _ => unreachable!(),
+    }) {
+        Branch::Left((data, st)) => {
+            let written = tcp.send(&data.0);
+            let st = system_user_channel.select_one(st, Written(written));
+            let segments = tcp.transmit();
+            let recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+            ControlFlow::Continue((tcp, recursive))
+        }
+        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+            Branch::Left((data, st)) => {
+                let written = tcp.send_urgent(&data.0);
+                let st = system_user_channel.select_one(st, Written(written));
+                let segments = tcp.transmit();
+                let recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                ControlFlow::Continue((tcp, recursive))
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((_close, st)) => {
+                    let (tcp, segments) = tcp.close();
+                    let st = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                    ControlFlow::Break(fin_wait_1(net_channel, tcp, st))
+                }
+                Branch::Right((_abort, st)) => {
+                    warn!("aborted by user");
+                    let remote_addr = tcp.remote_addr();
+                    let (_, rst) = tcp.abort();
+                    ControlFlow::Break(net_channel.select_one(st, remote_addr, rst))
+                }
+            },
+        },
+    }
+}
+
+/// Hand the data that came with the peer's FIN to our user, then tell it the
+/// peer has closed its side.
+fn deliver_last(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    system_user_channel: &mut CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>,
+    mut tcp: Tcp<CloseWait>,
+    deliver: ServerSystemDeliverLast,
+) -> End {
+    let urgent = tcp.urgent();
+    let data = tcp.read();
+    if data.is_empty() {
+        let st = system_user_channel.select_right_right(deliver.inner(), Close(()));
+        return close_wait(net_channel, system_user_channel, tcp, st);
+    }
+    info!("Got {:?} bytes with the FIN", data.len());
+
+    let st = match urgent {
+        Some(urgent) => {
+            info!("{} of them urgent", urgent);
+            let deliver = system_user_channel.select_left(deliver.inner(), Urgent(urgent));
+            system_user_channel.select_right_left(deliver.inner(), Data(data))
+        }
+        None => system_user_channel.select_right_left(deliver.inner(), Data(data)),
+    };
+
+    let (written, st) = match system_user_channel.offer_two(st, |net| match net {
+        NetRepresentation::Data(_) => Choice::Left,
+        NetRepresentation::UrgentData(_)
+        | NetRepresentation::Close(_)
+        | NetRepresentation::Abort(_) => Choice::Right,
+        _ => unreachable!(),
+    }) {
+        Branch::Left((data, st)) => (tcp.send(&data.0), st),
+        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+            Branch::Left((data, st)) => (tcp.send_urgent(&data.0), st),
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((_close, st)) => {
+                    let (tcp, fin) = tcp.close();
+                    let st = net_channel.select_one(st, tcp.remote_addr(), fin);
+                    return last_ack(net_channel, tcp, st);
+                }
+                Branch::Right((_abort, st)) => {
+                    warn!("aborted by user");
+                    let remote_addr = tcp.remote_addr();
+                    let (_, rst) = tcp.abort();
+                    return net_channel.select_one(st, remote_addr, rst);
+                }
+            },
+        },
+    };
+    let st = system_user_channel.select_one(st, Written(written));
+    let segments = tcp.transmit();
+    let st = net_channel.select_segments(st, tcp.remote_addr(), segments);
+    let drain = system_user_channel.select_one(st, Close(()));
+    match close_wait_drain(net_channel, system_user_channel, tcp, drain) {
+        ControlFlow::Continue((tcp, st)) => close_wait(net_channel, system_user_channel, tcp, st),
+        ControlFlow::Break(end) => end,
+    }
+}
+
+/// The peer has closed its side, keep sending whatever our user wants until it
+/// closes as well.
+fn close_wait(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    system_user_channel: &mut CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>,
+    mut tcp: Tcp<CloseWait>,
+    mut recursive: ServerSystemCloseWait,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        match system_user_channel.offer_two(st, |net| match net {
+            NetRepresentation::Data(_) => Choice::Left,
+            NetRepresentation::Close(_) | NetRepresentation::Abort(_) => Choice::Right,
+            _ => unreachable!(),
+        }) {
+            Branch::Left((data, st)) => {
+                let written = tcp.send(&data.0);
+                let st = system_user_channel.select_one(st, Written(written));
+                let segments = tcp.transmit();
+                let drain = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                match close_wait_drain(net_channel, system_user_channel, tcp, drain) {
+                    ControlFlow::Continue((drained, st)) => (tcp, recursive) = (drained, st),
+                    ControlFlow::Break(end) => return end,
+                }
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((_close, st)) => {
+                    let (tcp, fin) = tcp.close();
+                    let st = net_channel.select_one(st, tcp.remote_addr(), fin);
+                    return last_ack(net_channel, tcp, st);
+                }
+                Branch::Right((_abort, st)) => {
+                    let remote_addr = tcp.remote_addr();
+                    let (_, rst) = tcp.abort();
+                    return net_channel.select_one(st, remote_addr, rst);
+                }
+            },
+        }
+    }
+}
+
+/// Wait for everything our user wrote to be sent and acknowledged before
+/// handing control back to it. Breaks once the connection is over.
+fn close_wait_drain(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    system_user_channel: &mut CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>,
+    mut tcp: Tcp<CloseWait>,
+    mut drain: ServerSystemCloseWaitDrain,
+) -> ControlFlow<End, (Tcp<CloseWait>, ServerSystemCloseWait)> {
+    loop {
+        let st = drain.inner();
+        let drained = tcp.is_drained();
+        let tcp_for_picker = tcp.for_picker();
+        match net_channel.offer_two_filtered(
+            st,
+            |packet| match packet {
+                Some(packet) if tcp_for_picker.resets(&packet) => {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Left(packet.into())),
+                    )))))
+                }
+                Some(packet) if packet.rst() => Branch::Right(Nested::Right(Nested::Right(
+                    Nested::Right(Nested::Right(Nested::Right(Nested::Right(packet.into())))),
+                ))),
+                Some(packet) if packet.syn() => Branch::Right(Nested::Right(Nested::Right(
+                    Nested::Right(Nested::Right(Nested::Left(packet.into()))),
+                ))),
+                Some(packet) if packet.fin() => Branch::Right(Nested::Left(packet.into())),
+                Some(packet) => Branch::Left(packet.into()),
+                None if drained => Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                    Nested::Left(Timeout),
+                )))),
+                None if tcp_for_picker.probe_due() => {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Left(Timeout))))
+                }
+                None => Branch::Right(Nested::Right(Nested::Left(Timeout))),
+            },
+            &tcp,
+            if drained {
+                Some(Duration::ZERO)
+            } else {
+                tcp.send_timeout()
+            },
+        ) {
+            Branch::Left((ack, st)) => {
+                let resp;
+                (tcp, resp) = match tcp.recv_ack(&ack) {
+                    Reaction::Acceptable(tcp, resp, _) | Reaction::NotAcceptable(tcp, resp) => {
+                        (tcp, resp)
+                    }
+                    Reaction::Reset(_) => unreachable!(),
+                };
+                let mut segments = Segments(resp.into_iter().collect());
+                segments.0.extend(tcp.transmit().0);
+                drain = net_channel.select_segments(st, tcp.remote_addr(), segments);
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((fin, st)) => {
+                    let ack = tcp.recv_fin(&fin);
+                    drain = net_channel.select_one(st, tcp.remote_addr(), ack);
+                }
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((_timeout, st)) => {
+                        let segments = tcp.retransmission();
+                        drain = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                    }
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((_timeout, st)) => {
+                            let probe = tcp.probe();
+                            drain = net_channel.select_one(st, tcp.remote_addr(), probe);
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((_timeout, st)) => {
+                                return ControlFlow::Continue((tcp, st))
+                            }
+                            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                Branch::Left((syn, st)) => {
+                                    let segments = challenge_syn(&mut tcp, &syn);
+                                    drain = net_channel.select_segments(
+                                        st,
+                                        tcp.remote_addr(),
+                                        segments,
+                                    );
+                                }
+                                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                    Branch::Left((rst, st)) => {
+                                        reset(tcp, &rst);
+                                        return ControlFlow::Break(peer_gone(
+                                            system_user_channel,
+                                            st,
+                                        ));
+                                    }
+                                    Branch::Right((rst, st)) => {
+                                        let segments;
+                                        (tcp, segments) = challenge(tcp, &rst);
+                                        drain = net_channel.select_segments(
+                                            st,
+                                            tcp.remote_addr(),
+                                            segments,
+                                        );
+                                    }
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Our FIN followed the peer's, wait for it to be acknowledged.
+fn last_ack(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    mut tcp: Tcp<LastAck>,
+    mut recursive: ServerSystemLastAck,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        let tcp_for_picker = tcp.for_picker();
+        match net_channel.offer_two_filtered(
+            st,
+            |packet| match packet {
+                Some(packet) if tcp_for_picker.resets(&packet) => Branch::Right(Nested::Right(
+                    Nested::Right(Nested::Right(Nested::Right(Nested::Left(packet.into())))),
+                )),
+                Some(packet) if packet.rst() => Branch::Right(Nested::Right(Nested::Right(
+                    Nested::Right(Nested::Right(Nested::Right(packet.into()))),
+                ))),
+                Some(packet) if packet.syn() => Branch::Right(Nested::Right(Nested::Right(
+                    Nested::Right(Nested::Left(packet.into())),
+                ))),
+                Some(packet) if packet.fin() => Branch::Right(Nested::Left(packet.into())),
+                Some(packet)
+                    if tcp_for_picker.acceptable(&packet) && tcp_for_picker.acks_fin(&packet) =>
+                {
+                    Branch::Left(packet.into())
+                }
+                Some(packet) => Branch::Right(Nested::Right(Nested::Left(packet.into()))),
+                None => Branch::Right(Nested::Right(Nested::Right(Nested::Left(Timeout)))),
+            },
+            &tcp,
+            tcp.retransmission_timeout(),
+        ) {
+            Branch::Left((ack, end)) => match tcp.recv_ack(&ack) {
+                Reaction::Acceptable(_closed, _, _) => return end,
+                Reaction::NotAcceptable(_, _) => unreachable!(),
+                Reaction::Reset(_) => unreachable!(),
+            },
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((fin, st)) => {
+                    let ack = tcp.recv_fin(&fin);
+                    recursive = net_channel.select_one(st, tcp.remote_addr(), ack);
+                }
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((ack, st)) => {
+                        let segments = tcp.recv_ack_of_data(&ack);
+                        recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                    }
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((_timeout, st)) => {
+                            let fin = tcp.retransmission().expect("FIN not queued");
+                            recursive = net_channel.select_one(st, tcp.remote_addr(), fin);
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((syn, st)) => {
+                                let segments = challenge_syn(&mut tcp, &syn);
+                                recursive =
+                                    net_channel.select_segments(st, tcp.remote_addr(), segments);
+                            }
+                            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                Branch::Left((rst, end)) => {
+                                    reset(tcp, &rst);
+                                    return end;
+                                }
+                                Branch::Right((rst, st)) => {
+                                    let segments;
+                                    (tcp, segments) = challenge(tcp, &rst);
+                                    recursive = net_channel.select_segments(
+                                        st,
+                                        tcp.remote_addr(),
+                                        segments,
+                                    );
+                                }
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Keepalive probes went unanswered or the peer reset the connection, there
+/// is nobody to send our user's data to anymore.
+fn peer_gone(
+    system_user_channel: &mut CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>,
+    peer_gone: ServerSystemPeerGone,
+) -> End {
+    let st = peer_gone.inner();
+    match system_user_channel.offer_two(st, |net| match net {
+        NetRepresentation::Data(_) => Choice::Left,
+        NetRepresentation::Close(_) | NetRepresentation::Abort(_) => Choice::Right,
+        _ => unreachable!(),
+    }) {
+        Branch::Left((_data, st)) => system_user_channel.select_one(st, Close(())),
+        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+            Branch::Left((_close, end)) => end,
+            Branch::Right((_abort, end)) => end,
+        },
+    }
+}
+
+/// Our user has closed. Send what is left in the send buffer followed by our
+/// FIN, and wait for the peer to acknowledge it and to send its own.
+fn fin_wait_1(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    mut tcp: Tcp<FinWait1>,
+    mut recursive: ServerSystemFinWait1,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        let tcp_for_picker = tcp.for_picker();
+        let fin_due = tcp_for_picker.fin_due();
+        match net_channel.offer_two_filtered(
+            st,
+            |packet| {
+                let Some(packet) = packet else {
+                    return if fin_due {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Right(Nested::Left(Timeout)),
+                        )))))
+                    } else if tcp_for_picker.probe_due() {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Right(Nested::Right(Nested::Left(Timeout))),
+                        )))))
+                    } else {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Left(Timeout),
+                        )))))
+                    };
+                };
+                if tcp_for_picker.resets(&packet) {
+                    return Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Left(packet.into()),
+                        ))))),
+                    ))));
+                }
+                if packet.rst() {
+                    return Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Right(packet.into()),
+                        ))))),
+                    ))));
+                }
+                if packet.syn() {
+                    return Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Left(
+                            packet.into(),
+                        ))))),
+                    ))));
+                }
+                let acceptable = tcp_for_picker.acceptable(&packet);
+                match (packet.fin(), acceptable, tcp_for_picker.acks_fin(&packet)) {
+                    (false, true, true) => Branch::Left(packet.into()),
+                    (true, true, true) => Branch::Right(Nested::Left(packet.into())),
+                    // simultaneous close
+                    (true, true, false) => {
+                        Branch::Right(Nested::Right(Nested::Left(packet.into())))
+                    }
+                    (true, false, _) => {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Left(packet.into()))))
+                    }
+                    (false, _, _) => Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Left(packet.into()),
+                    )))),
+                }
+            },
+            &tcp,
+            if fin_due {
+                Some(Duration::ZERO)
+            } else {
+                tcp.send_timeout()
+            },
+        ) {
+            Branch::Left((ack, st)) => {
+                // Any data along with it is thrown away, our user has closed.
+                let (tcp, resp) = match tcp.recv_ack(&ack) {
+                    Reaction::Acceptable(tcp, resp, _) => (tcp, resp),
+                    Reaction::NotAcceptable(_, _) => unreachable!(),
+                    Reaction::Reset(_) => unreachable!(),
+                };
+                let st = net_channel.select_segments(
+                    st,
+                    tcp.remote_addr(),
+                    Segments(resp.into_iter().collect()),
+                );
+                return fin_wait_2(net_channel, tcp, st);
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((fin, st)) => match tcp.recv_fin(&fin) {
+                    Reaction::Acceptable(tcp, Some(ack), _) => {
+                        let st = net_channel.select_one(st, tcp.remote_addr(), ack);
+                        return time_wait(net_channel, tcp, st);
+                    }
+                    Reaction::Acceptable(_, None, _) => unreachable!(),
+                    Reaction::NotAcceptable(_, _) => unreachable!(),
+                    Reaction::Reset(_) => unreachable!(),
+                },
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((fin, st)) => match tcp.recv_simultaneous_fin(&fin) {
+                        Reaction::Acceptable(tcp, Some(ack), _) => {
+                            let st = net_channel.select_one(st, tcp.remote_addr(), ack);
+                            return closing(net_channel, tcp, st);
+                        }
+                        Reaction::Acceptable(_, None, _) => unreachable!(),
+                        Reaction::NotAcceptable(_, _) => unreachable!(),
+                        Reaction::Reset(_) => unreachable!(),
+                    },
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((fin, st)) => {
+                            warn!("FIN not acceptable or out of order");
+                            let ack;
+                            (tcp, ack) = match tcp.recv_fin(&fin) {
+                                Reaction::Acceptable(_, _, _) => unreachable!(),
+                                Reaction::NotAcceptable(tcp, ack) => (tcp, ack),
+                                Reaction::Reset(_) => unreachable!(),
+                            };
+                            recursive = net_channel.select_segments(
+                                st,
+                                tcp.remote_addr(),
+                                Segments(ack.into_iter().collect()),
+                            );
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((ack, st)) => {
+                                let segments = tcp.recv_ack_of_data(&ack);
+                                recursive =
+                                    net_channel.select_segments(st, tcp.remote_addr(), segments);
+                            }
+                            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                Branch::Left((_timeout, st)) => {
+                                    recursive = match tcp.retransmission() {
+                                        Some(Retransmission::Data(ack)) => {
+                                            net_channel.select_left(st, tcp.remote_addr(), ack)
+                                        }
+                                        Some(Retransmission::Fin(fin)) => {
+                                            net_channel.select_right(st, tcp.remote_addr(), fin)
+                                        }
+                                        None => unreachable!("timeout with nothing to retransmit"),
+                                    };
+                                }
+                                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                    Branch::Left((_timeout, st)) => {
+                                        let fin = tcp.send_fin();
+                                        recursive =
+                                            net_channel.select_one(st, tcp.remote_addr(), fin);
+                                    }
+                                    Branch::Right((nested, st)) => {
+                                        match nested_offer_two(st, nested) {
+                                            Branch::Left((_timeout, st)) => {
+                                                let probe = tcp.probe();
+                                                recursive = net_channel.select_one(
+                                                    st,
+                                                    tcp.remote_addr(),
+                                                    probe,
+                                                );
+                                            }
+                                            Branch::Right((nested, st)) => {
+                                                match nested_offer_two(st, nested) {
+                                                    Branch::Left((syn, st)) => {
+                                                        let segments =
+                                                            challenge_syn(&mut tcp, &syn);
+                                                        recursive = net_channel.select_segments(
+                                                            st,
+                                                            tcp.remote_addr(),
+                                                            segments,
+                                                        );
+                                                    }
+                                                    Branch::Right((nested, st)) => {
+                                                        match nested_offer_two(st, nested) {
+                                                            Branch::Left((rst, end)) => {
+                                                                reset(tcp, &rst);
+                                                                return end;
+                                                            }
+                                                            Branch::Right((rst, st)) => {
+                                                                let segments;
+                                                                (tcp, segments) =
+                                                                    challenge(tcp, &rst);
+                                                                recursive = net_channel
+                                                                    .select_segments(
+                                                                        st,
+                                                                        tcp.remote_addr(),
+                                                                        segments,
+                                                                    );
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Our FIN is acknowledged, wait for the peer to send its own.
+fn fin_wait_2(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    mut tcp: Tcp<FinWait2>,
+    mut recursive: ServerSystemFinWait2,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        let tcp_for_picker = tcp.for_picker();
+        match net_channel.offer_two_filtered(
+            st,
+            move |packet| {
+                let packet = packet.unwrap();
+                if tcp_for_picker.resets(&packet) {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Left(
+                        packet.into(),
+                    )))))
+                } else if packet.rst() {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                        packet.into(),
+                    )))))
+                } else if packet.syn() {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Left(packet.into()))))
+                } else if !packet.fin() {
+                    Branch::Left(packet.into())
+                } else {
+                    if tcp_for_picker.acceptable(&packet) {
+                        Branch::Right(Nested::Left(packet.into()))
+                    } else {
+                        Branch::Right(Nested::Right(Nested::Left(packet.into())))
+                    }
+                }
+            },
+            &tcp,
+            None,
+        ) {
+            Branch::Left((ack, st)) => {
+                // We have received data from the Client, but we
+                // will just throw it away, since our user has
+                // closed.
+                let resp;
+                (tcp, resp) = match tcp.recv_ack(&ack) {
+                    Reaction::Acceptable(tcp, resp, _) | Reaction::NotAcceptable(tcp, resp) => {
+                        (tcp, resp)
+                    }
+                    Reaction::Reset(_) => unreachable!(),
+                };
+                recursive = net_channel.select_segments(
+                    st,
+                    tcp.remote_addr(),
+                    Segments(resp.into_iter().collect()),
+                );
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((fin, st)) => {
+                    let (tcp, ack) = match tcp.recv_fin(&fin) {
+                        Reaction::Acceptable(tcp, Some(ack), _) => (tcp, ack),
+                        Reaction::Acceptable(_, None, _) => unreachable!(),
+                        Reaction::NotAcceptable(_, _) => unreachable!(),
+                        Reaction::Reset(_) => unreachable!(),
+                    };
+                    let st = net_channel.select_one(st, tcp.remote_addr(), ack);
+                    return time_wait(net_channel, tcp, st);
+                }
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((fin, st)) => {
+                        warn!("FIN not acceptable or out of order");
+                        let ack;
+                        (tcp, ack) = match tcp.recv_fin(&fin) {
+                            Reaction::Acceptable(_, _, _) => unreachable!(),
+                            Reaction::NotAcceptable(tcp, ack) => (tcp, ack),
+                            Reaction::Reset(_) => unreachable!(),
+                        };
+                        recursive = net_channel.select_segments(
+                            st,
+                            tcp.remote_addr(),
+                            Segments(ack.into_iter().collect()),
+                        );
+                    }
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((syn, st)) => {
+                            let segments = challenge_syn(&mut tcp, &syn);
+                            recursive =
+                                net_channel.select_segments(st, tcp.remote_addr(), segments);
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((rst, end)) => {
+                                reset(tcp, &rst);
+                                return end;
+                            }
+                            Branch::Right((rst, st)) => {
+                                let segments;
+                                (tcp, segments) = challenge(tcp, &rst);
+                                recursive =
+                                    net_channel.select_segments(st, tcp.remote_addr(), segments);
+                            }
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Both sides are closing, send what is left ahead of our FIN and wait for
+/// the peer to acknowledge it.
+fn closing(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    mut tcp: Tcp<Closing>,
+    mut recursive: ServerSystemClosing,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        let tcp_for_picker = tcp.for_picker();
+        let fin_due = tcp_for_picker.fin_due();
+        match net_channel.offer_two_filtered(
+            st,
+            |packet| {
+                let Some(packet) = packet else {
+                    return if fin_due {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Left(
+                            Timeout,
+                        )))))
+                    } else if tcp_for_picker.probe_due() {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                            Nested::Left(Timeout),
+                        )))))
+                    } else {
+                        Branch::Right(Nested::Right(Nested::Right(Nested::Left(Timeout))))
+                    };
+                };
+                if tcp_for_picker.resets(&packet) {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Right(Nested::Left(packet.into()))),
+                    )))))
+                } else if packet.rst() {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Right(Nested::Right(packet.into()))),
+                    )))))
+                } else if packet.syn() {
+                    Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                        Nested::Right(Nested::Left(packet.into())),
+                    )))))
+                } else if packet.fin() {
+                    Branch::Right(Nested::Left(packet.into()))
+                } else if tcp_for_picker.acceptable(&packet) && tcp_for_picker.acks_fin(&packet) {
+                    Branch::Left(packet.into())
+                } else {
+                    Branch::Right(Nested::Right(Nested::Left(packet.into())))
+                }
+            },
+            &tcp,
+            if fin_due {
+                Some(Duration::ZERO)
+            } else {
+                tcp.send_timeout()
+            },
+        ) {
+            Branch::Left((ack, st)) => {
+                let (tcp, resp) = match tcp.recv_ack(&ack) {
+                    Reaction::Acceptable(tcp, resp, _) => (tcp, resp),
+                    Reaction::NotAcceptable(_, _) => unreachable!(),
+                    Reaction::Reset(_) => unreachable!(),
+                };
+                let st = net_channel.select_segments(
+                    st,
+                    tcp.remote_addr(),
+                    Segments(resp.into_iter().collect()),
+                );
+                return time_wait(net_channel, tcp, st);
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((fin, st)) => {
+                    let ack = tcp.recv_fin(&fin);
+                    recursive = net_channel.select_one(st, tcp.remote_addr(), ack);
+                }
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((ack, st)) => {
+                        // ACK of earlier data or not acceptable, our FIN is still outstanding.
+                        let segments = tcp.recv_ack_of_data(&ack);
+                        recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                    }
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((_timeout, st)) => {
+                            recursive = match tcp.retransmission() {
+                                Some(Retransmission::Data(ack)) => {
+                                    net_channel.select_left(st, tcp.remote_addr(), ack)
+                                }
+                                Some(Retransmission::Fin(fin)) => {
+                                    net_channel.select_right(st, tcp.remote_addr(), fin)
+                                }
+                                None => unreachable!("timeout with nothing to retransmit"),
+                            };
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((_timeout, st)) => {
+                                let fin = tcp.send_fin();
+                                recursive = net_channel.select_one(st, tcp.remote_addr(), fin);
+                            }
+                            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                Branch::Left((_timeout, st)) => {
+                                    let probe = tcp.probe();
+                                    recursive =
+                                        net_channel.select_one(st, tcp.remote_addr(), probe);
+                                }
+                                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                    Branch::Left((syn, st)) => {
+                                        let segments = challenge_syn(&mut tcp, &syn);
+                                        recursive = net_channel.select_segments(
+                                            st,
+                                            tcp.remote_addr(),
+                                            segments,
+                                        );
+                                    }
+                                    Branch::Right((nested, st)) => {
+                                        match nested_offer_two(st, nested) {
+                                            Branch::Left((rst, end)) => {
+                                                reset(tcp, &rst);
+                                                return end;
+                                            }
+                                            Branch::Right((rst, st)) => {
+                                                let segments;
+                                                (tcp, segments) = challenge(tcp, &rst);
+                                                recursive = net_channel.select_segments(
+                                                    st,
+                                                    tcp.remote_addr(),
+                                                    segments,
+                                                );
+                                            }
+                                        }
+                                    }
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Linger in TIME-WAIT, re-ACKing any retransmitted FIN until 2*MSL passes.
+fn time_wait(
+    net_channel: &mut SmolChannel<RoleServerSystem, RoleClientSystem>,
+    mut tcp: Tcp<TimeWait>,
+    mut recursive: ServerSystemTimeWait,
+) -> End {
+    loop {
+        let st = recursive.inner();
+        let tcp_for_picker = tcp.for_picker();
+        let timeout = tcp.timeout();
+        match net_channel.offer_two_filtered(
+            st,
+            |packet| match packet {
+                Some(packet) if tcp_for_picker.resets(&packet) => {
+                    Branch::Right(Nested::Right(Nested::Left(packet.into())))
+                }
+                Some(packet) if packet.rst() => {
+                    Branch::Right(Nested::Right(Nested::Right(packet.into())))
+                }
+                Some(packet) => Branch::Left(packet.into()),
+                None => Branch::Right(Nested::Left(Timeout)),
+            },
+            &tcp,
+            Some(timeout),
+        ) {
+            Branch::Left((fin, st)) => {
+                let ack = tcp.recv_fin(&fin);
+                recursive = net_channel.select_one(st, tcp.remote_addr(), ack);
+            }
+            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                Branch::Left((_timeout, end)) => {
+                    info!("TIME-WAIT expired");
+                    return end;
+                }
+                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                    Branch::Left((rst, end)) => {
+                        reset(tcp, &rst);
+                        return end;
+                    }
+                    Branch::Right((rst, st)) => {
+                        let segments;
+                        (tcp, segments) = challenge(tcp, &rst);
+                        recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+                    }
+                },
+            },
+        }
+    }
+}
+
 fn main() -> Result<()> {
     pretty_env_logger::formatted_builder()
         .filter_level(log::LevelFilter::Info)
@@ -71,40 +1008,68 @@ fn main() -> Result<()> {
                 }
             };
 
+            // urgent bytes at the front of the next data
+            let mut urgent = 0;
             'top: loop {
                 let st = recursive.inner();
 
                 match user_system_channel.offer_two(st, |net| match net {
                     NetRepresentation::Data(_) => Choice::Left,
-                    NetRepresentation::Close(_) => Choice::Right,
+                    NetRepresentation::Urgent(_) | NetRepresentation::Close(_) => Choice::Right,
                     _ => unreachable!(),
                 }) {
                     Branch::Left((data, st)) => {
                         let mut message = data.0;
 
                         println!(
-                            "User received data: {:?}",
-                            std::str::from_utf8(&message).unwrap_or("<invalid utf8>")
+                            "User received data: {:?}, {} bytes urgent",
+                            std::str::from_utf8(&message).unwrap_or("<invalid utf8>"),
+                            urgent
                         );
 
                         if message.len() <= 1 {
-                            let st = user_system_channel.select_right(st, Close(()));
-                            user_system_channel.close(st);
+                            let st =
+                                user_system_channel.select_right_right(st, Nested::Left(Close(())));
+                            user_system_channel.close(nested_select_left(st));
+                            break 'top;
+                        }
+
+                        if message.strip_suffix(b"\n").unwrap_or(&message) == b"abort" {
+                            let st = user_system_channel
+                                .select_right_right(st, Nested::Right(Abort(())));
+                            user_system_channel.close(nested_select_right(st));
                             break 'top;
                         }
 
                         message
                             .split_mut(|b| *b == 0x0a)
                             .for_each(|line| line.reverse());
-                        recursive = user_system_channel.select_left(st, Data(message));
+                        let len = message.len();
+                        // urgent data is echoed back as urgent
+                        let st = if std::mem::take(&mut urgent) > 0 {
+                            user_system_channel.select_right_left(st, UrgentData(message))
+                        } else {
+                            user_system_channel.select_left(st, Data(message))
+                        };
+                        let (written, st) = user_system_channel.offer_one(st);
+                        if written.0 < len {
+                            warn!("send buffer full, {} bytes dropped", len - written.0);
+                        }
+                        recursive = st;
                         continue;
                     }
-                    Branch::Right((_close, recursive)) => {
-                        let st = recursive.inner();
-                        let st = user_system_channel.select_right(st, Close(()));
-                        user_system_channel.close(st);
-                        break 'top;
-                    }
+                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                        Branch::Left((notification, st)) => {
+                            urgent = notification.0;
+                            recursive = st;
+                        }
+                        Branch::Right((_close, recursive)) => {
+                            let st = recursive.inner();
+                            let st = user_system_channel.select_right_left(st, Close(()));
+                            user_system_channel.close(st);
+                            break 'top;
+                        }
+                    },
                 }
             }
         });
@@ -114,71 +1079,195 @@ fn main() -> Result<()> {
 
             let smol_lower = SmolLower::new(args.local_addr.into()).unwrap();
             let checksum_caps = smol_lower.checksum_caps();
+            let mtu = smol_lower.mtu();
             let mut net_channel =
                 SmolChannel::<RoleServerSystem, RoleClientSystem>::new(smol_lower);
             let st = ServerSystemSessionType::new();
-            let tcp = TcpClosed::new();
+            let mut config = TcpConfig::default();
+            if let Some(msl) = args.msl {
+                config.msl = Duration::from_secs(msl);
+            }
+            config.delayed_ack = args.delayed_ack.map(Duration::from_millis);
+            config.nodelay = args.nodelay;
+            if let Some(idle) = args.keepalive_idle {
+                let mut keepalive = Keepalive {
+                    idle: Duration::from_secs(idle),
+                    ..Keepalive::default()
+                };
+                if let Some(interval) = args.keepalive_interval {
+                    keepalive.interval = Duration::from_secs(interval);
+                }
+                if let Some(probes) = args.keepalive_probes {
+                    keepalive.probes = probes;
+                }
+                config.keepalive = Some(keepalive);
+            }
+            let tcp = TcpClosed::with_config(config);
 
             // await Open call from user
             let (_open, st) = system_user_channel.offer_one(st);
-            let tcp = tcp.open(LocalAddr {
-                addr: args.local_addr.into(),
-                port: 555,
-                checksum_caps,
-            } /* TODO take this from user */);
+            let tcp = tcp.open(
+                LocalAddr {
+                    addr: args.local_addr.into(),
+                    port: 555,
+                    checksum_caps,
+                    mtu,
+                }, /* TODO take this from user */
+                Some(args.cc),
+            );
 
             let st = system_user_channel.select_one(st, TcbCreated(()));
 
-            let (addr, syn, st) = net_channel.offer_one_with_addr(st, &tcp);
+            let mut tcp = tcp;
+            let mut listen: ServerSystemListen = st;
+            let (mut tcp, st) = 'listen: loop {
+                let st = listen.inner();
+                let (addr, syn, st) = net_channel.offer_one_with_addr(st, &tcp);
 
-            let (mut tcp, synack) = tcp.recv_syn(addr, &syn);
-            let mut syn_rcvd = net_channel.select_one(st, addr, synack);
+                let (mut syn_rcvd_tcp, synack) = tcp.recv_syn(addr, &syn);
+                let mut syn_rcvd = net_channel.select_one(st, addr, synack);
 
-            let (mut tcp, st) = loop {
-                let st = syn_rcvd.inner();
-                let tcp_for_picker = tcp.for_picker();
-                match net_channel.offer_two_filtered(
-                    st,
-                    |packet| {
-                        if let Some(packet) = packet {
-                            match tcp_for_picker.acceptable(&packet) {
-                                ReactionInner::Acceptable(_, _) => Branch::Left(packet.into()),
-                                _ => Branch::Right(packet.into()),
+                loop {
+                    let st = syn_rcvd.inner();
+                    let tcp_for_picker = syn_rcvd_tcp.for_picker();
+                    match net_channel.offer_two_filtered(
+                        st,
+                        |packet| match packet {
+                            Some(packet) if tcp_for_picker.acceptable(&packet) => {
+                                Branch::Left(packet.into())
                             }
-                        } else {
-                            unreachable!()
-                        }
-                    },
-                    &tcp,
-                    None,
-                ) {
-                    Branch::Left((acceptable, st)) => {
-                        let tcp = tcp
-                            .recv_ack(&acceptable)
-                            .empty_acceptable()
-                            .expect("First ACK must be empty");
-                        break (tcp, st);
-                    }
-                    Branch::Right((unacceptable, st)) => {
-                        let remote_addr = tcp.remote_addr();
-                        match tcp.recv_ack(&unacceptable) {
-                            Reaction::Acceptable(_, _, _) => unreachable!(),
-                            Reaction::NotAcceptable(tcp2, Some(resp)) => {
-                                let st = net_channel.select_left(st, tcp2.remote_addr(), resp);
-                                syn_rcvd = st;
-                                tcp = tcp2;
-                                continue;
+                            Some(packet) if tcp_for_picker.bad_ack(&packet) => {
+                                Branch::Right(Nested::Left(packet.into()))
+                            }
+                            Some(packet) if tcp_for_picker.retransmitted_syn(&packet) => {
+                                Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                                    Nested::Left(packet.into()),
+                                ))))
+                            }
+                            Some(packet) if packet.syn() => Branch::Right(Nested::Right(
+                                Nested::Right(Nested::Right(Nested::Right(Nested::Left(
+                                    packet.into(),
+                                )))),
+                            )),
+                            Some(packet) if tcp_for_picker.resets(&packet) => {
+                                Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                                    Nested::Right(Nested::Right(Nested::Left(packet.into()))),
+                                ))))
+                            }
+                            Some(packet) if packet.rst() => Branch::Right(Nested::Right(
+                                Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                                    Nested::Right(packet.into()),
+                                )))),
+                            )),
+                            Some(packet) => {
+                                Branch::Right(Nested::Right(Nested::Left(packet.into())))
                             }
-                            Reaction::NotAcceptable(_, None) => unreachable!(),
-                            Reaction::Reset(Some(rst)) => {
-                                let st = net_channel.select_right(st, remote_addr, rst);
+                            None => Branch::Right(Nested::Right(Nested::Right(Nested::Left(
+                                Timeout,
+                            )))),
+                        },
+                        &syn_rcvd_tcp,
+                        syn_rcvd_tcp.retransmission_timeout(),
+                    ) {
+                        Branch::Left((acceptable, st)) => {
+                            let tcp = syn_rcvd_tcp
+                                .recv_ack(&acceptable)
+                                .empty_acceptable()
+                                .expect("First ACK must be empty");
+                            break 'listen (tcp, st);
+                        }
+                        Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                            Branch::Left((bad_ack, st)) => {
+                                let remote_addr = syn_rcvd_tcp.remote_addr();
+                                let rst = match syn_rcvd_tcp.recv_ack(&bad_ack) {
+                                    Reaction::Reset(Some(rst)) => rst,
+                                    _ => unreachable!("an ACK outside of the handshake resets"),
+                                };
+                                let st = net_channel.select_one(st, remote_addr, rst);
                                 let end = system_user_channel.select_one(st, Close(()));
                                 net_channel.close(end);
                                 system_user_channel.close(end);
                                 return;
                             }
-                            Reaction::Reset(None) => unreachable!(),
-                        };
+                            Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                Branch::Left((unacceptable, st)) => {
+                                    let resp;
+                                    (syn_rcvd_tcp, resp) =
+                                        match syn_rcvd_tcp.recv_ack(&unacceptable) {
+                                            Reaction::NotAcceptable(tcp, resp) => (tcp, resp),
+                                            _ => unreachable!(),
+                                        };
+                                    syn_rcvd = net_channel.select_segments(
+                                        st,
+                                        syn_rcvd_tcp.remote_addr(),
+                                        Segments(resp.into_iter().collect()),
+                                    );
+                                }
+                                Branch::Right((nested, st)) => match nested_offer_two(st, nested)
+                                {
+                                    Branch::Left((_timeout, st)) => {
+                                        let syn_ack = syn_rcvd_tcp
+                                            .retransmission()
+                                            .expect("SYN-ACK not queued");
+                                        syn_rcvd = net_channel.select_one(
+                                            st,
+                                            syn_rcvd_tcp.remote_addr(),
+                                            syn_ack,
+                                        );
+                                    }
+                                    Branch::Right((nested, st)) => {
+                                        match nested_offer_two(st, nested) {
+                                            Branch::Left((syn, st)) => {
+                                                warn!("SYN retransmitted, SYN-ACK lost");
+                                                let syn_ack =
+                                                    syn_rcvd_tcp.recv_retransmitted_syn(&syn);
+                                                syn_rcvd = net_channel.select_one(
+                                                    st,
+                                                    syn_rcvd_tcp.remote_addr(),
+                                                    syn_ack,
+                                                );
+                                            }
+                                            Branch::Right((nested, st)) => {
+                                                match nested_offer_two(st, nested) {
+                                                    Branch::Left((syn, st)) => {
+                                                        warn!("unexpected SYN in SYN-RECEIVED");
+                                                        let challenge = syn_rcvd_tcp.recv_syn(&syn);
+                                                        syn_rcvd = net_channel.select_segments(
+                                                            st,
+                                                            syn_rcvd_tcp.remote_addr(),
+                                                            Segments(
+                                                                challenge.into_iter().collect(),
+                                                            ),
+                                                        );
+                                                    }
+                                                    Branch::Right((nested, st)) => {
+                                                        match nested_offer_two(st, nested) {
+                                                            Branch::Left((rst, st)) => {
+                                                                warn!("handshake reset by peer");
+                                                                tcp = syn_rcvd_tcp.recv_reset(&rst);
+                                                                listen = st;
+                                                                continue 'listen;
+                                                            }
+                                                            Branch::Right((rst, st)) => {
+                                                                let resp;
+                                                                (syn_rcvd_tcp, resp) =
+                                                                    challenge(syn_rcvd_tcp, &rst);
+                                                                syn_rcvd = net_channel
+                                                                    .select_segments(
+                                                                        st,
+                                                                        syn_rcvd_tcp.remote_addr(),
+                                                                        resp,
+                                                                    );
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                },
+                            },
+                        },
                     }
                 }
             };
@@ -186,235 +1275,244 @@ fn main() -> Result<()> {
             let mut recursive = system_user_channel.select_one(st, Connected(()));
             info!("established");
 
-            let mut last_timeout = Duration::from_millis(500);
-
             'top: loop {
                 let st = recursive.inner();
 
-                let timeout = {
-                    const MAX_TIMEOUT: Duration = Duration::from_secs(20);
-
-                    let timeout = last_timeout * 2;
-                    if timeout > MAX_TIMEOUT {
-                        MAX_TIMEOUT
-                    } else {
-                        timeout
-                    }
-                };
-
                 let tcp_for_picker = tcp.for_picker();
                 match net_channel.offer_two_filtered(
                     st,
                     move |packet| {
                         if let Some(packet) = packet {
-                            if packet.fin() {
-                                // TODO unacceptable FINs are not handled properly
-                                Branch::Right(Nested::Right(Nested::Left(packet.into())))
-                            } else {
-                                match tcp_for_picker.acceptable(&packet) {
-                                    ReactionInner::Acceptable(_, Some(_)) => {
-                                        Branch::Left(packet.into())
-                                    }
-                                    ReactionInner::Acceptable(_, None) => {
-                                        Branch::Right(Nested::Left(packet.into()))
-                                    }
-                                    _ => Branch::Right(Nested::Right(Nested::Right(Nested::Left(
-                                        packet.into(),
-                                    )))),
+                            if tcp_for_picker.resets(&packet) {
+                                return Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Left(packet.into())))))))))));
+                            }
+                            if packet.rst() {
+                                return Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(packet.into())))))))))));
+                            }
+                            if packet.syn() {
+                                return Branch::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Right(Nested::Left(packet.into()))))))))));
+                            }
+                            let fin = packet.fin();
+                            match (fin, tcp_for_picker.acceptable(&packet)) {
+                                (true, true) => Branch::Right(Nested::Right(Nested::Right(
+                                    Nested::Left(packet.into()),
+                                ))),
+                                (true, false) => Branch::Right(Nested::Right(Nested::Right(
+                                    Nested::Right(Nested::Left(packet.into())),
+                                ))),
+                                (false, true) if tcp_for_picker.ack_delayed(&packet) => {
+                                    Branch::Right(Nested::Left(packet.into()))
+                                }
+                                (false, true) if tcp_for_picker.delivers_data(&packet) => {
+                                    Branch::Left(packet.into())
                                 }
+                                (false, true) => {
+                                    Branch::Right(Nested::Right(Nested::Left(packet.into())))
+                                }
+                                (false, false) => Branch::Right(Nested::Right(Nested::Right(
+                                    Nested::Right(Nested::Right(Nested::Left(packet.into()))),
+                                ))),
                             }
+                        } else if tcp_for_picker.peer_gone() {
+                            Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                                Nested::Right(Nested::Right(Nested::Right(Nested::Right(
+                                    Nested::Left(Timeout),
+                                )))),
+                            ))))
+                        } else if tcp_for_picker.probe_due() {
+                            Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                                Nested::Right(Nested::Right(Nested::Right(Nested::Left(Timeout)))),
+                            ))))
                         } else {
-                            Branch::Right(Nested::Right(Nested::Right(Nested::Right(Timeout))))
+                            Branch::Right(Nested::Right(Nested::Right(Nested::Right(
+                                Nested::Right(Nested::Right(Nested::Left(Timeout))),
+                            ))))
                         }
                     },
                     &tcp,
-                    if tcp.retransmission_queue_is_empty() {
-                        None
-                    } else {
-                        Some(timeout)
-                    },
+                    tcp.next_timeout(),
                 ) {
                     Branch::Left((acceptable_with_data, st)) => {
-                        let resp;
-                        let data: &[u8];
-                        (tcp, resp, data) = match tcp.recv(&acceptable_with_data) {
-                            Reaction::Acceptable(tcp, Some(resp), Some(data)) => (tcp, resp, data),
-                            Reaction::Acceptable(_, Some(_), None) => unreachable!(),
-                            Reaction::Acceptable(_, None, _) => unreachable!(),
+                        let ack;
+                        (tcp, ack) = match tcp.recv(&acceptable_with_data) {
+                            Reaction::Acceptable(tcp, Some(ack), Some(_)) => (tcp, ack),
+                            Reaction::Acceptable(_, _, _) => unreachable!(),
                             Reaction::NotAcceptable(_, _) => unreachable!(),
                             Reaction::Reset(_) => unreachable!(),
                         };
-                        let st = net_channel.select_one(st, tcp.remote_addr(), resp);
-
-                        info!("Got {:?} bytes", data.len());
-
-                        let st = system_user_channel.select_one(st, Data(data.to_owned()));
-
-                        match system_user_channel.offer_two(st, |net| match net {
-                            NetRepresentation::Data(_) => Choice::Left,
-                            NetRepresentation::Close(_) => Choice::Right,
-                            _ => unreachable!(),
-                        }) {
-                            Branch::Left((data, st)) => {
-                                let tx = tcp.send(&data.0);
-                                let st = net_channel.select_one(st, tcp.remote_addr(), tx);
-                                recursive = st;
+                        let st = net_channel.select_one(st, tcp.remote_addr(), ack);
+                        match deliver(&mut net_channel, &mut system_user_channel, tcp, st) {
+                            ControlFlow::Continue((delivered, st)) => {
+                                (tcp, recursive) = (delivered, st)
                             }
-                            Branch::Right((_close, st)) => {
-                                let (tcp, fin) = tcp.close();
-                                let st = net_channel.select_one(st, tcp.remote_addr(), fin);
-
-                                match net_channel.offer_two_filtered(
-                                    st,
-                                    |packet| {
-                                        let packet = packet.unwrap();
-                                        if packet.fin() {
-                                            Branch::Right(packet.into()) // simultaneous close
-                                        } else {
-                                            Branch::Left(packet.into()) // ack of our fin hopefully
-                                        }
-                                    },
-                                    &tcp,
-                                    None,
-                                ) {
-                                    Branch::Left((ack, mut recursive)) => {
-                                        let mut tcp = tcp
-                                            .recv_ack(&ack)
-                                            .empty_acceptable()
-                                            .expect("ACK of FIN must be empty");
-
-                                        loop {
-                                            let st = recursive.inner();
-                                            match net_channel.offer_two_filtered(
-                                                st,
-                                                |packet| {
-                                                    let packet = packet.unwrap();
-                                                    if packet.fin() {
-                                                        Branch::Right(packet.into())
-                                                    } else {
-                                                        Branch::Left(packet.into())
-                                                    }
-                                                },
-                                                &tcp,
-                                                None,
-                                            ) {
-                                                Branch::Left((ack, st)) => {
-                                                    // We have received data from the Client, but we
-                                                    // will just throw it away, since our user has
-                                                    // closed.
-                                                    let ack = tcp.recv_ack(&ack);
-                                                    recursive = net_channel.select_one(
-                                                        st,
-                                                        tcp.remote_addr(),
-                                                        ack,
-                                                    );
-                                                    continue;
-                                                }
-                                                Branch::Right((fin, st)) => {
-                                                    let remote_addr = tcp.remote_addr();
-                                                    let ack = tcp.recv_fin(&fin);
-                                                    let end = net_channel.select_one(
-                                                        st,
-                                                        remote_addr,
-                                                        ack,
-                                                    );
-                                                    net_channel.close(end);
-                                                    system_user_channel.close(end);
-                                                    break 'top;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    Branch::Right((fin, st)) => {
-                                        let remote_addr = tcp.remote_addr();
-                                        match tcp.recv_fin(&fin) {
-                                            Reaction::Acceptable(_, None, _) => unreachable!(),
-                                            Reaction::Acceptable(_, Some(ack), None) => {
-                                                let end =
-                                                    net_channel.select_one(st, remote_addr, ack);
-                                                net_channel.close(end);
-                                                system_user_channel.close(end);
-                                                break 'top;
-                                            }
-                                            Reaction::Acceptable(_, Some(_), Some(_)) => {
-                                                not_in_st!("FIN with payload")
-                                            }
-                                            Reaction::NotAcceptable(_, _) => not_in_st!(),
-                                            Reaction::Reset(_) => not_in_st!(),
-                                        }
-                                    }
-                                };
+                            ControlFlow::Break(end) => {
+                                net_channel.close(end);
+                                system_user_channel.close(end);
+                                break 'top;
                             }
                         }
                     }
                     Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
-                        Branch::Left((acceptable_empty, st)) => {
-                            tcp = tcp.recv(&acceptable_empty).empty_acceptable().unwrap();
-                            recursive = st;
+                        Branch::Left((acceptable_with_data, st)) => {
+                            tcp = match tcp.recv(&acceptable_with_data) {
+                                Reaction::Acceptable(tcp, None, Some(_)) => tcp,
+                                Reaction::Acceptable(_, _, _) => unreachable!(),
+                                Reaction::NotAcceptable(_, _) => unreachable!(),
+                                Reaction::Reset(_) => unreachable!(),
+                            };
+                            match deliver(&mut net_channel, &mut system_user_channel, tcp, st) {
+                                ControlFlow::Continue((delivered, st)) => {
+                                    (tcp, recursive) = (delivered, st)
+                                }
+                                ControlFlow::Break(end) => {
+                                    net_channel.close(end);
+                                    system_user_channel.close(end);
+                                    break 'top;
+                                }
+                            }
                         }
                         Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
-                            Branch::Left((fin, st)) => {
-                                let (mut tcp, ack) = match tcp.recv_fin(&fin) {
-                                    Reaction::Acceptable(_, _, Some(_)) => todo!("payload in FIN"),
-                                    Reaction::Acceptable(tcp, Some(ack), None) => (tcp, ack),
-                                    Reaction::Acceptable(_, None, _) => unreachable!(),
-                                    Reaction::NotAcceptable(_, _) => not_in_st!("bad FIN"),
-                                    Reaction::Reset(_) => not_in_st!("reset from bad FIN"),
-                                };
-                                let st = net_channel.select_one(st, tcp.remote_addr(), ack);
-                                let mut recursive = system_user_channel.select_one(st, Close(()));
-                                loop {
-                                    let st = recursive.inner();
-                                    match system_user_channel.offer_two(st, |net| match net {
-                                        NetRepresentation::Data(_) => Choice::Left,
-                                        NetRepresentation::Close(_) => Choice::Right,
-                                        _ => unreachable!(),
-                                    }) {
-                                        Branch::Left((data, st)) => {
-                                            let tx = tcp.send(&data.0);
-                                            let st =
-                                                net_channel.select_one(st, tcp.remote_addr(), tx);
-                                            let (ack, st) =
-                                                net_channel.offer_one_filtered(st, &tcp);
-                                            tcp.recv_ack(&ack);
-                                            recursive = st;
-                                        }
-                                        Branch::Right((_close, st)) => {
-                                            let (tcp, fin) = tcp.close();
-                                            let st =
-                                                net_channel.select_one(st, tcp.remote_addr(), fin);
-                                            let (ack, end) =
-                                                net_channel.offer_one_filtered(st, &tcp);
-                                            tcp.recv_ack(&ack);
-                                            net_channel.close(end);
-                                            system_user_channel.close(end);
-                                            break 'top;
-                                        }
-                                    }
-                                }
+                            Branch::Left((acceptable_empty, st)) => {
+                                tcp = tcp.recv(&acceptable_empty).empty_acceptable().unwrap();
+                                let segments = tcp.transmit();
+                                recursive =
+                                    net_channel.select_segments(st, tcp.remote_addr(), segments);
                             }
                             Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
-                                Branch::Left((not_acceptable, st)) => {
-                                    warn!("Not acceptable");
-                                    let challenge;
-                                    (tcp, challenge) = match tcp.recv(&not_acceptable) {
-                                        Reaction::Acceptable(_, _, _) => {
-                                            unreachable!()
-                                        }
-                                        Reaction::NotAcceptable(tcp, Some(challenge)) => {
-                                            (tcp, challenge)
-                                        }
-                                        Reaction::NotAcceptable(_, None) => not_in_st!(),
-                                        Reaction::Reset(_) => not_in_st!(),
+                                Branch::Left((fin, st)) => {
+                                    let (tcp, ack) = match tcp.recv_fin(&fin) {
+                                        Reaction::Acceptable(tcp, Some(ack), _) => (tcp, ack),
+                                        Reaction::Acceptable(_, None, _) => unreachable!(),
+                                        Reaction::NotAcceptable(_, _) => not_in_st!("bad FIN"),
+                                        Reaction::Reset(_) => not_in_st!("reset from bad FIN"),
                                     };
-                                    recursive =
-                                        net_channel.select_one(st, tcp.remote_addr(), challenge);
-                                }
-                                Branch::Right((_, st)) => {
-                                    let ack = tcp.retransmission().expect("Nothing to retransmit");
-                                    last_timeout = timeout;
-                                    recursive = net_channel.select_one(st, tcp.remote_addr(), ack);
+                                    let st = net_channel.select_one(st, tcp.remote_addr(), ack);
+                                    let end = deliver_last(
+                                        &mut net_channel,
+                                        &mut system_user_channel,
+                                        tcp,
+                                        st,
+                                    );
+                                    net_channel.close(end);
+                                    system_user_channel.close(end);
+                                    break 'top;
                                 }
+                                Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                    Branch::Left((fin, st)) => {
+                                        warn!("FIN not acceptable or out of order");
+                                        let ack;
+                                        (tcp, ack) = match tcp.recv_fin(&fin) {
+                                            Reaction::Acceptable(_, _, _) => unreachable!(),
+                                            Reaction::NotAcceptable(tcp, ack) => (tcp, ack),
+                                            Reaction::Reset(_) => not_in_st!(),
+                                        };
+                                        recursive = net_channel.select_segments(
+                                            st,
+                                            tcp.remote_addr(),
+                                            Segments(ack.into_iter().collect()),
+                                        );
+                                    }
+                                    Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                        Branch::Left((not_acceptable, st)) => {
+                                            warn!("Not acceptable");
+                                            let challenge;
+                                            (tcp, challenge) = match tcp.recv(&not_acceptable) {
+                                                Reaction::Acceptable(_, _, _) => {
+                                                    unreachable!()
+                                                }
+                                                Reaction::NotAcceptable(tcp, challenge) => {
+                                                    (tcp, challenge)
+                                                }
+                                                Reaction::Reset(_) => not_in_st!(),
+                                            };
+                                            recursive = net_channel.select_segments(
+                                                st,
+                                                tcp.remote_addr(),
+                                                Segments(challenge.into_iter().collect()),
+                                            );
+                                        }
+                                        Branch::Right((nested, st)) => {
+                                            match nested_offer_two(st, nested) {
+                                                Branch::Left((_, st)) => {
+                                                    let segments = tcp.timeout();
+                                                    info!(
+                                                        "timeout, sending {} segments, rto is now {}, cwnd {} ssthresh {}",
+                                                        segments.0.len(),
+                                                        tcp.rto(),
+                                                        tcp.cwnd(),
+                                                        tcp.ssthresh()
+                                                    );
+                                                    recursive = net_channel.select_segments(
+                                                        st,
+                                                        tcp.remote_addr(),
+                                                        segments,
+                                                    );
+                                                }
+                                                Branch::Right((nested, st)) => {
+                                                    match nested_offer_two(st, nested) {
+                                                        Branch::Left((_, st)) => {
+                                                            let probe = tcp.probe();
+                                                            recursive = net_channel.select_one(
+                                                                st,
+                                                                tcp.remote_addr(),
+                                                                probe,
+                                                            );
+                                                        }
+                                                        Branch::Right((nested, st)) => {
+                                                            match nested_offer_two(st, nested) {
+                                                                Branch::Left((_, st)) => {
+                                                                    warn!("peer is gone");
+                                                                    let st = system_user_channel
+                                                                        .select_one(st, Close(()));
+                                                                    let end = peer_gone(
+                                                                        &mut system_user_channel,
+                                                                        st,
+                                                                    );
+                                                                    net_channel.close(end);
+                                                                    system_user_channel.close(end);
+                                                                    break 'top;
+                                                                }
+                                                                Branch::Right((nested, st)) => {
+                                                                    match nested_offer_two(st, nested) {
+Branch::Left((syn, st)) => {
+let segments = challenge_syn(&mut tcp, &syn);
+recursive = net_channel.select_segments(st, tcp.remote_addr(), segments);
+}
+Branch::Right((nested, st)) => match nested_offer_two(st, nested) {
+                                                                        Branch::Left((rst, st)) => {
+                                                                            reset(tcp, &rst);
+                                                                            let st = system_user_channel
+                                                                                .select_one(st, Close(()));
+                                                                            let end = peer_gone(
+                                                                                &mut system_user_channel,
+                                                                                st,
+                                                                            );
+                                                                            net_channel.close(end);
+                                                                            system_user_channel.close(end);
+                                                                            break 'top;
+                                                                        }
+                                                                        Branch::Right((rst, st)) => {
+                                                                            let segments;
+                                                                            (tcp, segments) =
+                                                                                challenge(tcp, &rst);
+                                                                            recursive = net_channel
+                                                                                .select_segments(
+                                                                                    st,
+                                                                                    tcp.remote_addr(),
+                                                                                    segments,
+                                                                                );
+                                                                        }
+},
+                                                                    }
+                                                                }
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    },
+                                },
                             },
                         },
                     },
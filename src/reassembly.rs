@@ -0,0 +1,179 @@
+use smoltcp::wire::TcpSeqNumber;
+
+/// Out-of-order data held until the hole in front of it is filled.
+#[derive(Clone, Debug, Default)]
+pub struct Reassembly {
+    /// Sorted by sequence number, neither overlapping nor adjacent.
+    ranges: Vec<(TcpSeqNumber, Vec<u8>)>,
+    /// Start of the most recently received segment, reported first in SACK.
+    last: Option<TcpSeqNumber>,
+}
+
+impl Reassembly {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Number of bytes held.
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// Hold `data` starting at `seq`, merging it with anything it overlaps or touches.
+    pub fn insert(&mut self, seq: TcpSeqNumber, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        self.last = Some(seq);
+        let mut start = seq;
+        let mut merged = data.to_vec();
+        let mut i = 0;
+        while i < self.ranges.len() {
+            let (s, d) = &self.ranges[i];
+            let (s, e) = (*s, *s + d.len());
+            let end = start + merged.len();
+            if e < start || end < s {
+                i += 1;
+                continue;
+            }
+
+            let (s, d) = self.ranges.remove(i);
+            if s < start {
+                let mut prefix = d[..start - s].to_vec();
+                prefix.append(&mut merged);
+                merged = prefix;
+                start = s;
+            }
+            if e > end {
+                merged.extend_from_slice(&d[d.len() - (e - end)..]);
+            }
+        }
+
+        let at = self
+            .ranges
+            .iter()
+            .position(|(s, _)| start < *s)
+            .unwrap_or(self.ranges.len());
+        self.ranges.insert(at, (start, merged));
+    }
+
+    /// The held ranges as SACK blocks, end exclusive. The block containing
+    /// the most recently received segment comes first.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc2018#section-4>
+    pub fn blocks(&self) -> Vec<(TcpSeqNumber, TcpSeqNumber)> {
+        let mut blocks: Vec<_> = self
+            .ranges
+            .iter()
+            .map(|(s, d)| (*s, *s + d.len()))
+            .collect();
+        if let Some(last) = self.last {
+            if let Some(i) = blocks.iter().position(|&(s, e)| s <= last && last < e) {
+                let block = blocks.remove(i);
+                blocks.insert(0, block);
+            }
+        }
+        blocks
+    }
+
+    /// Take out the data that continues exactly at `rcv_nxt`, if any. Data
+    /// that is already behind `rcv_nxt` is thrown away.
+    pub fn pop(&mut self, rcv_nxt: TcpSeqNumber) -> Option<Vec<u8>> {
+        while let Some((s, _)) = self.ranges.first() {
+            if *s > rcv_nxt {
+                return None;
+            }
+            let (s, d) = self.ranges.remove(0);
+            if s + d.len() > rcv_nxt {
+                return Some(d[rcv_nxt - s..].to_vec());
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: TcpSeqNumber = TcpSeqNumber(1000);
+
+    #[test]
+    fn out_of_order_data_waits_for_the_hole() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(BASE + 6, b"ghi");
+        reassembly.insert(BASE + 3, b"def");
+        assert_eq!(reassembly.len(), 6);
+        assert_eq!(reassembly.blocks(), vec![(BASE + 3, BASE + 9)]);
+        assert_eq!(reassembly.pop(BASE), None);
+        assert_eq!(reassembly.pop(BASE + 3).as_deref(), Some(&b"defghi"[..]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn overlapping_data_is_merged() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(BASE + 2, b"cde");
+        reassembly.insert(BASE + 8, b"ij");
+        // bridges both ranges, overlapping the first and touching the second
+        reassembly.insert(BASE + 4, b"efgh");
+        assert_eq!(reassembly.blocks(), vec![(BASE + 2, BASE + 10)]);
+        assert_eq!(reassembly.pop(BASE + 2).as_deref(), Some(&b"cdefghij"[..]));
+
+        // covers an existing range on both sides
+        reassembly.insert(BASE + 3, b"d");
+        reassembly.insert(BASE + 1, b"bcdef");
+        assert_eq!(reassembly.len(), 5);
+        assert_eq!(reassembly.pop(BASE + 1).as_deref(), Some(&b"bcdef"[..]));
+    }
+
+    #[test]
+    fn duplicates_are_held_once() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(BASE + 5, b"fgh");
+        reassembly.insert(BASE + 5, b"fgh");
+        reassembly.insert(BASE + 6, b"g");
+        reassembly.insert(BASE + 5, b"");
+        assert_eq!(reassembly.len(), 3);
+        assert_eq!(reassembly.blocks(), vec![(BASE + 5, BASE + 8)]);
+    }
+
+    #[test]
+    fn most_recent_block_is_reported_first() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(BASE + 10, b"k");
+        reassembly.insert(BASE + 20, b"u");
+        reassembly.insert(BASE + 2, b"c");
+        assert_eq!(
+            reassembly.blocks(),
+            vec![
+                (BASE + 2, BASE + 3),
+                (BASE + 10, BASE + 11),
+                (BASE + 20, BASE + 21)
+            ]
+        );
+        reassembly.insert(BASE + 11, b"l");
+        assert_eq!(reassembly.blocks()[0], (BASE + 10, BASE + 12));
+    }
+
+    #[test]
+    fn pop_trims_and_drops_what_was_already_received() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(BASE, b"ab");
+        reassembly.insert(BASE + 4, b"efgh");
+        // the first range is entirely behind RCV.NXT, the second partially
+        assert_eq!(reassembly.pop(BASE + 6).as_deref(), Some(&b"gh"[..]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn data_across_the_sequence_wraparound() {
+        let mut reassembly = Reassembly::default();
+        let rcv_nxt = TcpSeqNumber(i32::MAX - 1);
+        reassembly.insert(rcv_nxt + 4, b"efgh");
+        reassembly.insert(rcv_nxt + 2, b"cd");
+        assert_eq!(reassembly.blocks(), vec![(rcv_nxt + 2, rcv_nxt + 8)]);
+        assert_eq!(reassembly.pop(rcv_nxt + 2).as_deref(), Some(&b"cdefgh"[..]));
+    }
+}
@@ -0,0 +1,182 @@
+use smoltcp::wire::{TcpPacket, TcpSeqNumber};
+use std::collections::VecDeque;
+
+/// Everything sent but not acknowledged yet, starting at SND.UNA. Data is
+/// kept as a byte range rather than as the segments it went out in, so
+/// retransmissions can be cut anew from whatever is still unacknowledged.
+#[derive(Clone, Debug)]
+pub struct RetransmitQueue {
+    /// Sequence number of the front of the queue.
+    start: TcpSeqNumber,
+    /// Our SYN or SYN-ACK. It carries the handshake options and goes out
+    /// again unchanged.
+    syn: Option<TcpPacket<Vec<u8>>>,
+    data: VecDeque<u8>,
+    /// Our FIN follows the data.
+    fin: bool,
+}
+
+impl Default for RetransmitQueue {
+    fn default() -> Self {
+        RetransmitQueue {
+            start: TcpSeqNumber(0),
+            syn: None,
+            data: VecDeque::new(),
+            fin: false,
+        }
+    }
+}
+
+impl RetransmitQueue {
+    pub fn is_empty(&self) -> bool {
+        self.syn.is_none() && self.data.is_empty() && !self.fin
+    }
+
+    /// Sequence number right after the queue.
+    fn end(&self) -> TcpSeqNumber {
+        self.start + usize::from(self.syn.is_some()) + self.data.len() + usize::from(self.fin)
+    }
+
+    /// Remember a segment that went out for the first time.
+    pub fn push(&mut self, packet: &TcpPacket<Vec<u8>>) {
+        if self.is_empty() {
+            self.start = packet.seq_number();
+        }
+        debug_assert!(
+            packet.seq_number() == self.end(),
+            "gap in the retransmission queue"
+        );
+        if packet.syn() {
+            self.syn = Some(packet.clone());
+            return;
+        }
+        self.data
+            .extend(TcpPacket::new_unchecked(packet.as_ref()).payload());
+        self.fin |= packet.fin();
+    }
+
+    /// Everything before `ack` is acknowledged, drop it.
+    pub fn ack(&mut self, ack: TcpSeqNumber) {
+        if ack <= self.start {
+            return;
+        }
+        let mut acked = (ack - self.start).min(self.end() - self.start);
+        self.start += acked;
+        if self.syn.is_some() {
+            self.syn = None;
+            acked -= 1;
+        }
+        let bytes = acked.min(self.data.len());
+        self.data.drain(..bytes);
+        if acked > bytes {
+            self.fin = false;
+        }
+    }
+
+    /// The SYN at the front, if it is not acknowledged yet.
+    pub fn syn(&self) -> Option<&TcpPacket<Vec<u8>>> {
+        self.syn.as_ref()
+    }
+
+    /// Up to `max_len` bytes of data starting at `seq`, coalescing whatever
+    /// separate segments they were first sent in. Also tells whether the
+    /// FIN fits right after them.
+    pub fn segment(&self, seq: TcpSeqNumber, max_len: usize) -> (Vec<u8>, bool) {
+        let data_start = self.start + usize::from(self.syn.is_some());
+        let from = if seq > data_start {
+            (seq - data_start).min(self.data.len())
+        } else {
+            0
+        };
+        let to = (from + max_len).min(self.data.len());
+        let payload = self.data.range(from..to).copied().collect();
+        (payload, self.fin && to == self.data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::wire::{IpAddress, TcpControl, TcpRepr};
+
+    fn packet(seq: i32, control: TcpControl, payload: &[u8]) -> TcpPacket<Vec<u8>> {
+        let repr = TcpRepr {
+            src_port: 1,
+            dst_port: 2,
+            control,
+            seq_number: TcpSeqNumber(seq),
+            ack_number: None,
+            window_len: 0,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None; 3],
+            payload,
+        };
+        let mut buf = vec![0; repr.buffer_len()];
+        let addr = IpAddress::v4(0, 0, 0, 0);
+        repr.emit(
+            &mut TcpPacket::new_unchecked(&mut buf),
+            &addr,
+            &addr,
+            &Default::default(),
+        );
+        TcpPacket::new_unchecked(buf)
+    }
+
+    #[test]
+    fn partial_acks_trim_the_front() {
+        let mut queue = RetransmitQueue::default();
+        queue.push(&packet(10, TcpControl::Syn, b""));
+        queue.push(&packet(11, TcpControl::None, b"ab"));
+        queue.push(&packet(13, TcpControl::Psh, b"cd"));
+        queue.push(&packet(15, TcpControl::Fin, b""));
+        assert!(queue.syn().is_some());
+
+        queue.ack(TcpSeqNumber(12));
+        assert!(queue.syn().is_none());
+        assert_eq!(queue.segment(TcpSeqNumber(12), 2), (b"bc".to_vec(), false));
+        assert_eq!(queue.segment(TcpSeqNumber(12), 8), (b"bcd".to_vec(), true));
+
+        queue.ack(TcpSeqNumber(16));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn stale_and_excessive_acks_are_bounded() {
+        let mut queue = RetransmitQueue::default();
+        queue.push(&packet(100, TcpControl::Psh, b"abcd"));
+
+        // old and duplicate ACKs change nothing
+        queue.ack(TcpSeqNumber(90));
+        queue.ack(TcpSeqNumber(100));
+        assert_eq!(
+            queue.segment(TcpSeqNumber(100), 8),
+            (b"abcd".to_vec(), false)
+        );
+
+        queue.ack(TcpSeqNumber(102));
+        queue.ack(TcpSeqNumber(101));
+        assert_eq!(queue.segment(TcpSeqNumber(100), 8), (b"cd".to_vec(), false));
+
+        // never past what was sent
+        queue.ack(TcpSeqNumber(200));
+        assert!(queue.is_empty());
+        queue.push(&packet(104, TcpControl::Psh, b"e"));
+        assert_eq!(queue.segment(TcpSeqNumber(104), 8), (b"e".to_vec(), false));
+    }
+
+    #[test]
+    fn fin_stays_until_acknowledged_itself() {
+        let mut queue = RetransmitQueue::default();
+        queue.push(&packet(-3, TcpControl::Psh, b"abcdef"));
+        queue.push(&packet(3, TcpControl::Fin, b""));
+
+        // across the sequence number wraparound
+        queue.ack(TcpSeqNumber(3));
+        assert!(!queue.is_empty());
+        assert_eq!(queue.segment(TcpSeqNumber(3), 8), (Vec::new(), true));
+        queue.ack(TcpSeqNumber(4));
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use smoltcp::wire::TcpSeqNumber;
+
+/// Sequence ranges above SND.UNA the peer reported holding via SACK.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc2018>
+#[derive(Clone, Debug, Default)]
+pub struct Scoreboard {
+    /// Sorted, neither overlapping nor adjacent, end exclusive.
+    blocks: Vec<(TcpSeqNumber, TcpSeqNumber)>,
+}
+
+impl Scoreboard {
+    /// Record a SACK block, merging it with anything it overlaps or touches.
+    pub fn insert(&mut self, left: TcpSeqNumber, right: TcpSeqNumber) {
+        if right <= left {
+            return;
+        }
+
+        let (mut start, mut end) = (left, right);
+        self.blocks.retain(|&(s, e)| {
+            if e < start || end < s {
+                return true;
+            }
+            if s < start {
+                start = s;
+            }
+            if e > end {
+                end = e;
+            }
+            false
+        });
+
+        let at = self
+            .blocks
+            .iter()
+            .position(|(s, _)| start < *s)
+            .unwrap_or(self.blocks.len());
+        self.blocks.insert(at, (start, end));
+    }
+
+    /// Everything before `snd_una` is cumulatively acknowledged, forget about it.
+    pub fn acked(&mut self, snd_una: TcpSeqNumber) {
+        self.blocks.retain(|&(_, e)| e > snd_una);
+        if let Some((s, _)) = self.blocks.first_mut() {
+            if *s < snd_una {
+                *s = snd_una;
+            }
+        }
+    }
+
+    /// The first block that ends after `seq`.
+    pub fn block_after(&self, seq: TcpSeqNumber) -> Option<(TcpSeqNumber, TcpSeqNumber)> {
+        self.blocks.iter().find(|&&(_, e)| e > seq).copied()
+    }
+
+    /// The highest sequence number the peer reported holding.
+    pub fn highest(&self) -> Option<TcpSeqNumber> {
+        self.blocks.last().map(|&(_, e)| e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: TcpSeqNumber = TcpSeqNumber(1000);
+
+    fn blocks(scoreboard: &Scoreboard) -> Vec<(usize, usize)> {
+        scoreboard
+            .blocks
+            .iter()
+            .map(|&(s, e)| (s - BASE, e - BASE))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_merged_and_sorted() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.insert(BASE + 300, BASE + 400);
+        scoreboard.insert(BASE + 100, BASE + 200);
+        assert_eq!(blocks(&scoreboard), vec![(100, 200), (300, 400)]);
+
+        // touching one, overlapping the other
+        scoreboard.insert(BASE + 200, BASE + 350);
+        assert_eq!(blocks(&scoreboard), vec![(100, 400)]);
+
+        // a duplicate report and an empty block change nothing
+        scoreboard.insert(BASE + 150, BASE + 250);
+        scoreboard.insert(BASE + 500, BASE + 500);
+        assert_eq!(blocks(&scoreboard), vec![(100, 400)]);
+
+        scoreboard.insert(BASE + 50, BASE + 450);
+        assert_eq!(blocks(&scoreboard), vec![(50, 450)]);
+        assert_eq!(scoreboard.highest(), Some(BASE + 450));
+    }
+
+    #[test]
+    fn cumulative_ack_trims_the_scoreboard() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.insert(BASE + 100, BASE + 200);
+        scoreboard.insert(BASE + 300, BASE + 400);
+        assert_eq!(scoreboard.block_after(BASE), Some((BASE + 100, BASE + 200)));
+        assert_eq!(
+            scoreboard.block_after(BASE + 200),
+            Some((BASE + 300, BASE + 400))
+        );
+
+        scoreboard.acked(BASE + 200);
+        assert_eq!(blocks(&scoreboard), vec![(300, 400)]);
+        scoreboard.acked(BASE + 350);
+        assert_eq!(blocks(&scoreboard), vec![(350, 400)]);
+        scoreboard.acked(BASE + 400);
+        assert_eq!(scoreboard.highest(), None);
+        assert_eq!(scoreboard.block_after(BASE), None);
+    }
+
+    #[test]
+    fn blocks_across_the_sequence_wraparound() {
+        let mut scoreboard = Scoreboard::default();
+        let snd_una = TcpSeqNumber(i32::MAX - 100);
+        scoreboard.insert(snd_una + 150, snd_una + 250);
+        scoreboard.insert(snd_una + 50, snd_una + 150);
+        assert_eq!(
+            scoreboard.block_after(snd_una),
+            Some((snd_una + 50, snd_una + 250))
+        );
+
+        scoreboard.acked(snd_una + 200);
+        assert_eq!(
+            scoreboard.block_after(snd_una),
+            Some((snd_una + 200, snd_una + 250))
+        );
+        assert_eq!(scoreboard.highest(), Some(snd_una + 250));
+    }
+}
@@ -47,12 +47,7 @@ where
         A: Action,
         F: ChannelFilter<TcpPacket<Vec<u8>>>,
     {
-        let (addr, buf) = loop {
-            let (addr, buf) = self.lower.recv(None).expect("recv failed");
-            if filter.filter(addr, &buf) {
-                break (addr, buf);
-            }
-        };
+        let (addr, buf) = self.recv_filtered(None, filter).expect("recv failed");
         (addr, M::from_packet(buf), A::new())
     }
 
@@ -84,15 +79,10 @@ where
         F: ChannelFilter<TcpPacket<Vec<u8>>>,
     {
         let deadline = timeout.map(|t| Instant::now() + t);
-        let buf = loop {
-            let (addr, buf) = match self.lower.recv(deadline) {
-                Ok(m) => m,
-                Err(RecvError::Timeout) => break None,
-                Err(_) => panic!("recv failed"),
-            };
-            if filter.filter(addr, &buf) {
-                break Some(buf);
-            }
+        let buf = match self.recv_filtered(deadline, filter) {
+            Ok((_, buf)) => Some(buf),
+            Err(RecvError::Timeout) => None,
+            Err(_) => panic!("recv failed"),
         };
         match picker(buf) {
             Branch::Left(m) => Branch::Left((m, A1::new())),
@@ -100,6 +90,29 @@ where
         }
     }
 
+    /// Receive until a packet passes `filter`. Rejected packets that belong
+    /// to no connection are answered with a RST.
+    fn recv_filtered<F>(
+        &mut self,
+        deadline: Option<Instant>,
+        filter: &F,
+    ) -> Result<(Ipv4Address, TcpPacket<Vec<u8>>), RecvError>
+    where
+        F: ChannelFilter<TcpPacket<Vec<u8>>>,
+    {
+        loop {
+            let (addr, buf) = self.lower.recv(deadline)?;
+            if filter.filter(addr, &buf) {
+                return Ok((addr, buf));
+            }
+            if let Some(rst) = filter.reset(addr, &buf) {
+                self.lower
+                    .send(addr, rst.packet().as_ref())
+                    .expect("send failed");
+            }
+        }
+    }
+
     pub fn select_one<M, A>(&mut self, _o: SelectOne<R2, M, A>, to: Ipv4Address, message: M) -> A
     where
         M: SmolMessage,
@@ -112,6 +125,24 @@ where
         A::new()
     }
 
+    pub fn select_segments<A>(
+        &mut self,
+        _o: SelectOne<R2, Segments, A>,
+        to: Ipv4Address,
+        segments: Segments,
+    ) -> A
+    where
+        A: Action,
+        R1: Role,
+        R2: Role,
+    {
+        for segment in segments.0 {
+            let buf = segment.packet().as_ref();
+            self.lower.send(to, buf).expect("send failed");
+        }
+        A::new()
+    }
+
     pub fn select_left<M1, M2, A1, A2>(
         &mut self,
         _o: SelectTwo<R2, M1, M2, A1, A2>,
@@ -188,8 +219,16 @@ macro_rules! smol_message {
     };
 }
 
-smol_message!(Syn { +syn -ack -fin -rst });
+// a SYN arriving once the connection is synchronized is challenged whatever
+// else it carries
+smol_message!(Syn { +syn -rst });
 smol_message!(SynAck { +syn +ack -fin -rst });
 smol_message!(Ack { -syn +ack -fin -rst });
 smol_message!(FinAck { -syn +ack +fin -rst });
-smol_message!(Rst { -syn -ack -fin +rst });
+// the peer may or may not set ACK in a reset
+smol_message!(Rst { -syn -fin +rst });
+
+/// Zero or more [Ack] segments carrying data, as many as the sender's
+/// algorithm allows at the moment. Selecting an empty burst sends nothing.
+pub struct Segments(pub Vec<Ack>);
+impl Message for Segments {}
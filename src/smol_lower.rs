@@ -1,4 +1,4 @@
-use log::{debug, info};
+use log::{debug, info, warn};
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{wait as phy_wait, ChecksumCapabilities, Device, Medium, TunTapInterface};
 use smoltcp::socket::raw::{self, RecvError as SmolRecvError};
@@ -9,6 +9,9 @@ use smoltcp::wire::{
 use std::os::fd::AsRawFd;
 use thiserror::Error;
 
+use crate::smol_channel::SmolMessage;
+use crate::tcp::reset_unmatched;
+
 pub struct SmolLower<'a> {
     addr: Ipv4Address,
     listen_port: u16,
@@ -75,6 +78,10 @@ impl SmolLower<'_> {
         self.device.capabilities().checksum
     }
 
+    pub fn mtu(&self) -> usize {
+        self.device.capabilities().max_transmission_unit
+    }
+
     pub fn send(&mut self, dst: Ipv4Address, payload: &[u8]) -> anyhow::Result<()> {
         let caps = self.checksum_caps();
         let socket = self.sockets.get_mut::<raw::Socket>(self.raw_sock_handle);
@@ -126,14 +133,23 @@ impl SmolLower<'_> {
 
                 assert_eq!(ipv4_packet.next_header(), IpProtocol::Tcp);
 
+                let src_addr = ipv4_packet.src_addr();
                 let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload().to_owned())
                     .map_err(RecvError::MalformedTcp)?;
 
                 if tcp_packet.dst_port() != self.listen_port {
+                    // nothing on this port, the connection is CLOSED
+                    debug!("resetting segment to port {}", tcp_packet.dst_port());
+                    let caps = self.checksum_caps();
+                    if let Some(rst) = reset_unmatched(self.addr, src_addr, &caps, &tcp_packet) {
+                        if let Err(err) = self.send(src_addr, rst.packet().as_ref()) {
+                            warn!("failed to send reset: {}", err);
+                        }
+                    }
                     continue;
                 }
 
-                return Ok((ipv4_packet.src_addr(), tcp_packet));
+                return Ok((src_addr, tcp_packet));
             }
 
             phy_wait(self.device.as_raw_fd(), deadline.map(|t| t - timestamp)).unwrap();
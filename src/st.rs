@@ -160,6 +160,10 @@ where
 {
 }
 
+/// A select with three branches, as the `St!` macro nests it.
+pub type SelectThree<R, M1, M2, M3, A1, A2, A3> =
+    SelectTwo<R, M1, Nested<M2, M3>, A1, SelectTwo<NestRole, M2, M3, A2, A3>>;
+
 pub fn nested_offer_two<M1, M2, A1, A2>(
     _o: OfferTwo<NestRole, M1, M2, A1, A2>,
     nested: Nested<M1, M2>,
@@ -176,5 +180,28 @@ where
     }
 }
 
+/// Step into the left branch of a nested select. The message already went
+/// out with the outer select, wrapped in [Nested].
+pub fn nested_select_left<M1, M2, A1, A2>(_o: SelectTwo<NestRole, M1, M2, A1, A2>) -> A1
+where
+    M1: Message,
+    M2: Message,
+    A1: Action,
+    A2: Action,
+{
+    A1::new()
+}
+
+/// Step into the right branch of a nested select, see [nested_select_left].
+pub fn nested_select_right<M1, M2, A1, A2>(_o: SelectTwo<NestRole, M1, M2, A1, A2>) -> A2
+where
+    M1: Message,
+    M2: Message,
+    A1: Action,
+    A2: Action,
+{
+    A2::new()
+}
+
 pub struct Timeout;
 impl Message for Timeout {}
@@ -39,6 +39,12 @@ macro_rules! St {
     }) ] => {
         OfferTwo<$peer, $msg1, Nest![$($msgs),*], St![$($tail1).*], St![ (NestRole & {$($msgs $(.$tails)*),*}) ]>
     };
+    [ ($peer:ident + {
+        $msg1:ident $(.$tail1:tt)*
+        $(,$msgs:ident $(.$tails:tt)*)*$(,)?
+    }) ] => {
+        SelectTwo<$peer, $msg1, Nest![$($msgs),*], St![$($tail1).*], St![ (NestRole + {$($msgs $(.$tails)*),*}) ]>
+    };
 }
 pub(crate) use St;
 
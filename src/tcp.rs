@@ -1,34 +1,127 @@
 use log::{debug, info, warn};
 use smoltcp::{
     phy::ChecksumCapabilities,
-    wire::{IpAddress, Ipv4Address, TcpControl, TcpPacket, TcpRepr, TcpSeqNumber},
+    time::{Duration, Instant},
+    wire::{
+        IpAddress, Ipv4Address, TcpControl, TcpPacket, TcpRepr, TcpSeqNumber, IPV4_HEADER_LEN,
+        TCP_HEADER_LEN,
+    },
 };
 use std::{
     any::{type_name, TypeId},
+    borrow::Cow,
     collections::VecDeque,
     marker::PhantomData,
+    ops::Deref,
+    sync::Arc,
 };
 
-use crate::smol_channel::{Ack, FinAck, Rst, SmolMessage, Syn, SynAck};
+use crate::challenge_ack::ChallengeAckLimit;
+use crate::congestion::{CongestionAlgorithm, CongestionControl};
+use crate::iss::{IssGenerator, Rfc6528Iss};
+use crate::reassembly::Reassembly;
+use crate::retransmit_queue::RetransmitQueue;
+use crate::scoreboard::Scoreboard;
+use crate::smol_channel::{Ack, FinAck, Rst, Segments, SmolMessage, Syn, SynAck};
+use crate::timestamps::{self, Timestamp};
+
+/// Send MSS assumed when the peer does not tell us otherwise.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.7.1>
+const DEFAULT_SND_MSS: u16 = 536;
 
 #[derive(Clone, Debug)]
 pub struct LocalAddr {
     pub addr: Ipv4Address,
     pub checksum_caps: ChecksumCapabilities,
     pub port: u16,
+    /// MTU of the underlying link, bounds the MSS we advertise.
+    pub mtu: usize,
+}
+
+impl LocalAddr {
+    /// The largest segment we can receive, advertised in our SYN.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.7.1>
+    fn mss(&self) -> u16 {
+        let mss = self.mtu.saturating_sub(IPV4_HEADER_LEN + TCP_HEADER_LEN);
+        mss.min(usize::from(u16::MAX)) as u16
+    }
+
+    /// Eff.snd.MSS, given the MSS option the peer sent in its SYN, if any.
+    fn effective_snd_mss(&self, peer_mss: Option<u16>) -> u16 {
+        peer_mss.unwrap_or(DEFAULT_SND_MSS).min(self.mss()).max(1)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct RemoteAddr {
-    addr: Ipv4Address,
-    port: u16,
+    pub addr: Ipv4Address,
+    pub port: u16,
 }
 
 pub trait ChannelFilter<T> {
     fn filter(&self, from_addr: Ipv4Address, packet: &T) -> bool;
+
+    /// Reply to a packet [Self::filter] rejected because it belongs to no
+    /// connection.
+    fn reset(&self, _from_addr: Ipv4Address, _packet: &T) -> Option<Rst> {
+        None
+    }
+}
+
+/// RST in reply to a segment for a connection that does not exist, so the
+/// peer fails fast instead of retransmitting until it times out. `None` if
+/// the segment is a RST itself.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.1>
+pub fn reset_unmatched<T>(
+    local_addr: Ipv4Address,
+    remote_addr: Ipv4Address,
+    checksum_caps: &ChecksumCapabilities,
+    packet: &TcpPacket<T>,
+) -> Option<Rst>
+where
+    T: AsRef<[u8]>,
+{
+    if packet.rst() {
+        return None;
+    }
+    let (seq_number, ack_number) = if packet.ack() {
+        (packet.ack_number(), None)
+    } else {
+        (
+            TcpSeqNumber(0),
+            Some(packet.seq_number() + packet.segment_len()),
+        )
+    };
+    let repr = TcpRepr {
+        src_port: packet.dst_port(),
+        dst_port: packet.src_port(),
+        control: TcpControl::Rst,
+        seq_number,
+        ack_number,
+        window_len: 0,
+        window_scale: None,
+        max_seg_size: None,
+        sack_permitted: false,
+        sack_ranges: [None, None, None],
+        payload: &[],
+    };
+
+    let mut buf = vec![0; repr.buffer_len()];
+
+    repr.emit(
+        &mut TcpPacket::new_unchecked(&mut buf),
+        &IpAddress::from(local_addr),
+        &IpAddress::from(remote_addr),
+        checksum_caps,
+    );
+
+    Some(Rst::from_packet(TcpPacket::new_unchecked(buf)))
 }
 
-mod tcp_state {
+pub mod tcp_state {
     macro_rules! impl_tcp_state {
         ($($t:ident),*) => {
             $(
@@ -39,7 +132,17 @@ mod tcp_state {
         }
     }
 
-    impl_tcp_state!(SynRcvd, Established, FinWait1, FinWait2, CloseWait, LastAck);
+    impl_tcp_state!(
+        SynSent,
+        SynRcvd,
+        Established,
+        FinWait1,
+        FinWait2,
+        CloseWait,
+        LastAck,
+        Closing,
+        TimeWait
+    );
 
     pub trait TcpState: Clone {}
 }
@@ -51,18 +154,292 @@ struct Tcb {
     snd_una: TcpSeqNumber,
     snd_nxt: TcpSeqNumber,
 
-    snd_wnd: u16,
+    snd_wnd: u32,
+    /// MAX.SND.WND, the largest window the peer ever advertised.
+    max_snd_wnd: u32,
+    /// Window scale the peer applies to the windows it advertises.
+    snd_wnd_shift: u8,
     snd_wl1: TcpSeqNumber,
     snd_wl2: TcpSeqNumber,
+    /// Eff.snd.MSS, the largest payload we put in a segment.
+    snd_mss: u16,
+    /// Both sides sent SACK-permitted in their SYN.
+    sack_permitted: bool,
+    /// SND.UP, the end of the urgent data we sent, until it is acknowledged.
+    snd_up: Option<TcpSeqNumber>,
+    /// Our user has closed, the FIN goes out once the send buffer is empty.
+    fin_pending: bool,
 
     // irs: TcpSeqNumber,
     rcv_nxt: TcpSeqNumber,
-    rcv_wnd: u16,
+    rcv_wnd: u32,
+    /// Window scale we apply to the windows we advertise.
+    rcv_wnd_shift: u8,
+    /// The window opened since the last segment we sent.
+    window_update_pending: bool,
+    /// When the delayed ACK timer fires, if we owe the peer an ACK.
+    ack_deadline: Option<Instant>,
+    /// Nagle's algorithm is off.
+    nodelay: bool,
+    /// RCV.UP, the end of the urgent data the peer sent.
+    rcv_up: Option<TcpSeqNumber>,
+    /// RCV.UP moved and the user has not been told yet.
+    urgent_pending: bool,
+
+    rtt: RttEstimator,
+    /// End sequence number and send time of the segment currently being timed.
+    rtt_probe: Option<(TcpSeqNumber, Instant)>,
+    /// When the retransmission timer fires, if it is running.
+    rto_deadline: Option<Instant>,
+    /// When the next zero window probe is due, while the peer's window is closed.
+    persist_deadline: Option<Instant>,
+    /// Zero window probes sent without the window opening.
+    persist_backoff: u32,
+    /// When we last heard from the peer.
+    last_recv: Instant,
+    /// Keepalive probes sent since then.
+    keepalive_probes: u32,
+    /// When TIME-WAIT ends, set once it starts.
+    time_wait_deadline: Option<Instant>,
+
+    /// Duplicate ACKs received in a row.
+    dup_acks: u32,
+    /// `recover` from NewReno, the highest sequence number sent when fast
+    /// recovery started. `None` outside of fast recovery.
+    recover: Option<TcpSeqNumber>,
+    /// Temporary increase of the congestion window during fast recovery.
+    cwnd_inflation: usize,
+    /// The first unacknowledged segment goes out again with the next
+    /// transmission, set by fast retransmit and NewReno partial ACKs.
+    retransmit_pending: bool,
+
+    /// Random offset of our timestamp clock.
+    ts_offset: u32,
+    /// TS.Recent and when it was last updated, `None` unless both sides
+    /// send timestamps.
+    ts_recent: Option<(u32, Instant)>,
+    /// Last.ACK.sent, the acknowledgment number of the last segment we sent.
+    last_ack_sent: TcpSeqNumber,
+}
+
+impl Tcb {
+    /// TSval for a segment sent now, a millisecond clock.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-5.4>
+    fn ts_val(&self) -> u32 {
+        (Instant::now().total_millis() as u32).wrapping_add(self.ts_offset)
+    }
+
+    /// Whether a timestamp is not older than TS.Recent.
+    fn ts_not_older(&self, ts: Timestamp) -> bool {
+        match self.ts_recent {
+            Some((recent, _)) => ts.val.wrapping_sub(recent) as i32 >= 0,
+            None => true,
+        }
+    }
+}
+
+/// TS.Recent is too old to be compared against after this long idle.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-5.5>
+const PAWS_IDLE: Duration = Duration::from_secs(24 * 24 * 60 * 60);
+
+/// How a segment is going to be received, see [Tcp::check].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Verdict {
+    /// Outside the receive window or rejected by PAWS, ACKed unless it is a RST.
+    OutOfWindow,
+    /// Dropped without a reply, e.g. a segment without ACK.
+    Ignore,
+    /// A RST at RCV.NXT, or one acknowledging our SYN, resets the connection.
+    Reset,
+    /// Answered with a challenge ACK, e.g. a RST not at RCV.NXT or an ACK
+    /// of data we never sent.
+    Challenge,
+    /// The ACK does not belong to our handshake, the peer gets a RST.
+    BadAck,
+    /// The ACK is processed, the text is dropped as the receive window is closed.
+    WindowClosed,
+    /// The ACK is processed, the text waits for the hole in front of it to be filled.
+    OutOfOrder,
+    /// The ACK is processed and the text is received.
+    InOrder,
+}
+
+/// A parsed incoming segment, along with the options `TcpRepr` does not cover.
+struct Segment<'a> {
+    repr: TcpRepr<'a>,
+    timestamp: Option<Timestamp>,
+    /// SEG.UP, if the URG flag is set.
+    urgent: Option<u16>,
+}
+
+impl<'a> Deref for Segment<'a> {
+    type Target = TcpRepr<'a>;
+
+    fn deref(&self) -> &TcpRepr<'a> {
+        &self.repr
+    }
+}
+
+/// Round-trip time estimator computing the retransmission timeout.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc6298>
+#[derive(Copy, Clone, Debug)]
+struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl RttEstimator {
+    const INITIAL_RTO: Duration = Duration::from_secs(1);
+    const MIN_RTO: Duration = Duration::from_secs(1);
+    const MAX_RTO: Duration = Duration::from_secs(60);
+    /// Clock granularity, G in the RFC.
+    const GRANULARITY: Duration = Duration::from_millis(1);
+
+    fn new() -> Self {
+        RttEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: Self::INITIAL_RTO,
+        }
+    }
+
+    fn sample(&mut self, r: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(r);
+                self.rttvar = r / 2;
+            }
+            Some(srtt) => {
+                let delta = if srtt > r { srtt - r } else { r - srtt };
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + r) / 8);
+            }
+        }
+
+        let srtt = self.srtt.unwrap();
+        let var = (self.rttvar * 4).max(Self::GRANULARITY);
+        self.rto = (srtt + var).clamp(Self::MIN_RTO, Self::MAX_RTO);
+        debug!("rtt sample {}, srtt {}, rto {}", r, srtt, self.rto);
+    }
+
+    fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(Self::MAX_RTO);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpConfig {
+    /// Maximum segment lifetime. TIME-WAIT lingers for twice this long.
+    pub msl: Duration,
+    /// Initial send sequence number generator.
+    pub iss: Arc<dyn IssGenerator>,
+    /// How many bytes of user data can wait to be sent.
+    pub send_buffer_size: usize,
+    /// How many received bytes can wait for the user to read them. This
+    /// bounds the receive window, which cannot exceed 65535 bytes unless
+    /// the peer agrees to window scaling.
+    pub recv_buffer_size: usize,
+    /// Congestion control algorithm of new connections, unless
+    /// [TcpClosed::open] or [TcpClosed::connect] picks another one.
+    pub congestion: CongestionAlgorithm,
+    /// How long the ACK of received data may wait for outgoing data to ride
+    /// on, `None` acknowledges every segment right away.
+    pub delayed_ack: Option<Duration>,
+    /// Disable Nagle's algorithm, small writes go out right away.
+    pub nodelay: bool,
+    /// Probe idle connections, `None` never does.
+    pub keepalive: Option<Keepalive>,
+    /// Challenge ACK budget, shared with the clones of this config.
+    pub challenge_acks: Arc<ChallengeAckLimit>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            msl: Duration::from_secs(120),
+            iss: Arc::new(Rfc6528Iss::new()),
+            send_buffer_size: 256 * 1024,
+            recv_buffer_size: 256 * 1024,
+            congestion: CongestionAlgorithm::default(),
+            delayed_ack: None,
+            nodelay: false,
+            keepalive: None,
+            challenge_acks: Arc::new(ChallengeAckLimit::default()),
+        }
+    }
+}
+
+/// When to probe an idle connection and when to give up on the peer.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.4>
+#[derive(Copy, Clone, Debug)]
+pub struct Keepalive {
+    /// How long the connection has to be idle before the first probe.
+    pub idle: Duration,
+    /// Time between unanswered probes.
+    pub interval: Duration,
+    /// Unanswered probes after which the peer is considered gone.
+    pub probes: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            idle: Duration::from_secs(2 * 60 * 60),
+            interval: Duration::from_secs(75),
+            probes: 9,
+        }
+    }
+}
+
+fn time_left(deadline: Instant) -> Duration {
+    let now = Instant::now();
+    if deadline > now {
+        deadline - now
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Upper bound of the exponentially backed off persist timer.
+const PERSIST_MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Duplicate ACKs that trigger a fast retransmit.
+const DUP_ACK_THRESHOLD: u32 = 3;
+
+/// Largest shift count allowed by the window scale option.
+///
+/// Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-2.3>
+const MAX_WND_SHIFT: u8 = 14;
+
+/// The smallest window scale that lets us advertise the whole receive buffer.
+fn rcv_wnd_shift(config: &TcpConfig) -> u8 {
+    let mut shift = 0;
+    while shift < MAX_WND_SHIFT && config.recv_buffer_size >> shift > usize::from(u16::MAX) {
+        shift += 1;
+    }
+    shift
 }
 
-pub struct TcpClosed;
+/// The largest window that can be advertised with the given scale.
+fn max_wnd(shift: u8) -> usize {
+    usize::from(u16::MAX) << shift
+}
+
+fn initial_rcv_wnd(config: &TcpConfig, shift: u8) -> u32 {
+    config.recv_buffer_size.min(max_wnd(shift)) as u32
+}
+
+pub struct TcpClosed {
+    config: TcpConfig,
+}
 pub struct TcpListen {
     local: LocalAddr,
+    config: TcpConfig,
 }
 
 #[derive(Clone, Debug)]
@@ -70,7 +447,17 @@ pub struct Tcp<State> {
     local: LocalAddr,
     remote: RemoteAddr,
     tcb: Tcb,
-    retransmission: VecDeque<TcpPacket<Vec<u8>>>,
+    retransmission: RetransmitQueue,
+    /// Which parts of the retransmission queue the peer already holds.
+    scoreboard: Scoreboard,
+    cc: Box<dyn CongestionControl>,
+    /// User data not sent yet.
+    send_buffer: VecDeque<u8>,
+    /// Received data that arrived ahead of `rcv_nxt`.
+    reassembly: Reassembly,
+    /// Received data the user has not read yet.
+    recv_buffer: VecDeque<u8>,
+    config: TcpConfig,
     _marker: PhantomData<State>,
 }
 
@@ -78,11 +465,134 @@ pub struct TcpForPicker<S>(Tcp<S>);
 
 impl TcpClosed {
     pub fn new() -> Self {
-        TcpClosed {}
+        Self::with_config(TcpConfig::default())
+    }
+
+    pub fn with_config(config: TcpConfig) -> Self {
+        TcpClosed { config }
+    }
+
+    /// Passive open. Connections use `congestion` control, or
+    /// [TcpConfig::congestion] if it is `None`.
+    pub fn open(mut self, local: LocalAddr, congestion: Option<CongestionAlgorithm>) -> TcpListen {
+        if let Some(congestion) = congestion {
+            self.config.congestion = congestion;
+        }
+        TcpListen {
+            local,
+            config: self.config,
+        }
     }
 
-    pub fn open(self, local: LocalAddr) -> TcpListen {
-        TcpListen { local }
+    /// Active open. Emits the initial SYN towards `remote`. The connection
+    /// uses `congestion` control, or [TcpConfig::congestion] if it is `None`.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.1>
+    pub fn connect(
+        mut self,
+        local: LocalAddr,
+        remote: RemoteAddr,
+        congestion: Option<CongestionAlgorithm>,
+    ) -> (Tcp<SynSent>, Syn) {
+        if let Some(congestion) = congestion {
+            self.config.congestion = congestion;
+        }
+        let iss = self.config.iss.generate(&local, &remote);
+        let rcv_wnd_shift = rcv_wnd_shift(&self.config);
+        let mut tcb = Tcb {
+            // we know nothing about the peer yet, these are
+            // overwritten once the SYN-ACK arrives.
+            rcv_nxt: TcpSeqNumber(0),
+            rcv_wnd: initial_rcv_wnd(&self.config, rcv_wnd_shift),
+            rcv_wnd_shift,
+            window_update_pending: false,
+            ack_deadline: None,
+            nodelay: self.config.nodelay,
+            rcv_up: None,
+            urgent_pending: false,
+
+            snd_wl1: TcpSeqNumber(0),
+            snd_wl2: iss,
+            snd_mss: local.effective_snd_mss(None),
+            sack_permitted: false,
+            snd_up: None,
+
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            max_snd_wnd: 0,
+            snd_wnd_shift: 0,
+            rtt: RttEstimator::new(),
+            rtt_probe: None,
+            rto_deadline: None,
+            persist_deadline: None,
+            persist_backoff: 0,
+            fin_pending: false,
+            last_recv: Instant::now(),
+            keepalive_probes: 0,
+            time_wait_deadline: None,
+            dup_acks: 0,
+            recover: None,
+            cwnd_inflation: 0,
+            retransmit_pending: false,
+            ts_offset: rand::random(),
+            ts_recent: None,
+            last_ack_sent: TcpSeqNumber(0),
+        };
+
+        let syn = TcpRepr {
+            src_port: local.port,
+            dst_port: remote.port,
+            control: TcpControl::Syn,
+            seq_number: iss,
+            ack_number: None,
+            // the window in a SYN is never scaled
+            window_len: tcb.rcv_wnd.min(u32::from(u16::MAX)) as u16,
+            window_scale: Some(tcb.rcv_wnd_shift),
+            max_seg_size: Some(local.mss()),
+            sack_permitted: true,
+            sack_ranges: [None, None, None],
+            payload: &[],
+        };
+        tcb.snd_nxt += syn.segment_len();
+
+        let mut syn_data = vec![0; syn.buffer_len()];
+        syn.emit(
+            &mut TcpPacket::new_unchecked(&mut syn_data),
+            &IpAddress::from(local.addr),
+            &IpAddress::from(remote.addr),
+            &local.checksum_caps,
+        );
+        // offer timestamps, there is nothing to echo yet
+        let syn_data = timestamps::insert(
+            syn_data,
+            Timestamp {
+                val: tcb.ts_val(),
+                ecr: 0,
+            },
+            &IpAddress::from(local.addr),
+            &IpAddress::from(remote.addr),
+            &local.checksum_caps,
+        );
+        let syn = TcpPacket::new_unchecked(syn_data);
+
+        let mut tcp = Tcp {
+            local,
+            remote,
+            tcb,
+            retransmission: Default::default(),
+            scoreboard: Default::default(),
+            // replaced once the SYN-ACK tells us the MSS
+            cc: self.config.congestion.build(usize::from(tcb.snd_mss)),
+            send_buffer: Default::default(),
+            reassembly: Default::default(),
+            recv_buffer: Default::default(),
+            config: self.config,
+            _marker: PhantomData,
+        };
+        tcp.queue_retransmission(&syn);
+
+        (tcp, Syn::from_packet(syn))
     }
 }
 
@@ -90,6 +600,7 @@ impl TcpListen {
     // TODO look at unifying this with Tcp<T>.
 
     pub fn recv_syn(self, remote: Ipv4Address, syn: &Syn) -> (Tcp<SynRcvd>, SynAck) {
+        let syn_ts = timestamps::parse(syn.packet().as_ref());
         let syn = TcpRepr::parse(
             &TcpPacket::new_unchecked(syn.packet().as_ref()),
             &IpAddress::from(remote),
@@ -98,22 +609,60 @@ impl TcpListen {
         )
         .unwrap();
 
-        let iss = TcpSeqNumber(123); // TODO generate random
+        let remote = RemoteAddr {
+            addr: remote,
+            port: syn.src_port,
+        };
+        let iss = self.config.iss.generate(&self.local, &remote);
+        // Both sides scale their windows only if both sent the option.
+        // Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-2.2>
+        let snd_mss = self.local.effective_snd_mss(syn.max_seg_size);
+        let (snd_wnd_shift, rcv_wnd_shift) = match syn.window_scale {
+            Some(shift) => (shift.min(MAX_WND_SHIFT), rcv_wnd_shift(&self.config)),
+            None => (0, 0),
+        };
         let mut tcb = Tcb {
             // irs: syn.seq_number,
             rcv_nxt: syn.seq_number + syn.segment_len(),
-            rcv_wnd: 64000,
+            rcv_wnd: initial_rcv_wnd(&self.config, rcv_wnd_shift),
+            rcv_wnd_shift,
+            window_update_pending: false,
+            ack_deadline: None,
+            nodelay: self.config.nodelay,
+            rcv_up: None,
+            urgent_pending: false,
 
             // strictly speaking these should be set only when we get the first ACK
             // but let's set them to sensible values immediately
             // so they don't have to be Option<_>.
             snd_wl1: syn.seq_number,
             snd_wl2: iss,
+            snd_mss,
+            sack_permitted: syn.sack_permitted,
+            snd_up: None,
 
             // iss,
             snd_una: iss,
             snd_nxt: iss,
-            snd_wnd: syn.window_len,
+            snd_wnd: u32::from(syn.window_len),
+            max_snd_wnd: u32::from(syn.window_len),
+            snd_wnd_shift,
+            rtt: RttEstimator::new(),
+            rtt_probe: None,
+            rto_deadline: None,
+            persist_deadline: None,
+            persist_backoff: 0,
+            fin_pending: false,
+            last_recv: Instant::now(),
+            keepalive_probes: 0,
+            time_wait_deadline: None,
+            dup_acks: 0,
+            recover: None,
+            cwnd_inflation: 0,
+            retransmit_pending: false,
+            ts_offset: rand::random(),
+            ts_recent: syn_ts.map(|ts| (ts.val, Instant::now())),
+            last_ack_sent: syn.seq_number + syn.segment_len(),
         };
 
         let resp = TcpRepr {
@@ -122,10 +671,10 @@ impl TcpListen {
             control: TcpControl::Syn,
             seq_number: iss,
             ack_number: Some(tcb.rcv_nxt),
-            window_len: tcb.rcv_wnd,
-            window_scale: None,
-            max_seg_size: None,
-            sack_permitted: false,
+            window_len: tcb.rcv_wnd.min(u32::from(u16::MAX)) as u16,
+            window_scale: syn.window_scale.map(|_| tcb.rcv_wnd_shift),
+            max_seg_size: Some(self.local.mss()),
+            sack_permitted: tcb.sack_permitted,
             sack_ranges: [None, None, None],
             payload: &[],
         };
@@ -135,23 +684,40 @@ impl TcpListen {
         resp.emit(
             &mut TcpPacket::new_unchecked(&mut resp_data),
             &IpAddress::from(self.local.addr),
-            &IpAddress::from(remote),
+            &IpAddress::from(remote.addr),
             &self.local.checksum_caps,
         );
-
-        (
-            Tcp {
-                local: self.local,
-                remote: RemoteAddr {
-                    addr: remote,
-                    port: syn.src_port,
+        if let Some((recent, _)) = tcb.ts_recent {
+            resp_data = timestamps::insert(
+                resp_data,
+                Timestamp {
+                    val: tcb.ts_val(),
+                    ecr: recent,
                 },
-                tcb,
-                retransmission: Default::default(),
-                _marker: PhantomData,
-            },
-            SynAck::from_packet(TcpPacket::new_unchecked(resp_data)),
-        )
+                &IpAddress::from(self.local.addr),
+                &IpAddress::from(remote.addr),
+                &self.local.checksum_caps,
+            );
+        }
+
+        let syn_ack = TcpPacket::new_unchecked(resp_data);
+
+        let mut tcp = Tcp {
+            local: self.local,
+            remote,
+            tcb,
+            retransmission: Default::default(),
+            scoreboard: Default::default(),
+            cc: self.config.congestion.build(usize::from(snd_mss)),
+            send_buffer: Default::default(),
+            reassembly: Default::default(),
+            recv_buffer: Default::default(),
+            config: self.config,
+            _marker: PhantomData,
+        };
+        tcp.queue_retransmission(&syn_ack);
+
+        (tcp, SynAck::from_packet(syn_ack))
     }
 }
 
@@ -173,11 +739,27 @@ where
             false
         }
     }
+
+    /// Only an ACK can be for a connection that no longer exists, anything
+    /// else without SYN is dropped.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.2>
+    fn reset(&self, remote_addr: Ipv4Address, packet: &TcpPacket<T>) -> Option<Rst> {
+        if !packet.ack() {
+            return None;
+        }
+        reset_unmatched(
+            self.local.addr,
+            remote_addr,
+            &self.local.checksum_caps,
+            packet,
+        )
+    }
 }
 
 impl<T, U> ChannelFilter<TcpPacket<U>> for Tcp<T>
 where
-    T: TcpState + Clone,
+    T: TcpState + Clone + 'static,
     U: AsRef<[u8]>,
 {
     fn filter(&self, remote_addr: Ipv4Address, packet: &TcpPacket<U>) -> bool {
@@ -185,25 +767,58 @@ where
             info!("ignoring packet to wrong address");
             return false;
         }
-        if packet.dst_port() == self.local.port && packet.src_port() == self.remote.port {
-            true
-        } else {
+        if packet.dst_port() != self.local.port || packet.src_port() != self.remote.port {
             info!("ignoring packet to wrong port");
-            false
+            return false;
+        }
+        if !packet.ack() && !packet.syn() && !packet.rst() {
+            // Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
+            info!("ignoring segment without ACK");
+            return false;
+        }
+        if TypeId::of::<T>() == TypeId::of::<TimeWait>() && !packet.fin() && !packet.rst() {
+            // Only a retransmitted FIN or a reset is of interest in TIME-WAIT.
+            info!("ignoring non-FIN in TimeWait state");
+            return false;
+        }
+        true
+    }
+
+    /// Segments from anyone but our peer, there is no other connection and
+    /// nobody listening anymore.
+    fn reset(&self, remote_addr: Ipv4Address, packet: &TcpPacket<U>) -> Option<Rst> {
+        if remote_addr == self.remote.addr
+            && packet.dst_port() == self.local.port
+            && packet.src_port() == self.remote.port
+        {
+            return None;
         }
+        reset_unmatched(
+            self.local.addr,
+            remote_addr,
+            &self.local.checksum_caps,
+            packet,
+        )
     }
 }
 
+/// A segment going out again once our FIN is queued, which may still be
+/// data sent ahead of it.
+pub enum Retransmission {
+    Data(Ack),
+    Fin(FinAck),
+}
+
 #[must_use]
 pub enum ReactionInner<'a> {
-    Acceptable(Option<Ack>, Option<&'a [u8]>),
+    Acceptable(Option<Ack>, Option<Cow<'a, [u8]>>),
     NotAcceptable(Option<Ack>),
     Reset(Option<Rst>),
 }
 
 #[must_use]
 pub enum Reaction<'a, Ta, Tn> {
-    Acceptable(Ta, Option<Ack>, Option<&'a [u8]>),
+    Acceptable(Ta, Option<Ack>, Option<Cow<'a, [u8]>>),
     NotAcceptable(Tn, Option<Ack>),
     Reset(Option<Rst>),
 }
@@ -244,7 +859,338 @@ where
         self.retransmission.is_empty()
     }
 
+    /// Whether everything our user sent has gone out and been acknowledged.
+    pub fn is_drained(&self) -> bool {
+        self.send_buffer.is_empty() && self.retransmission.is_empty()
+    }
+
+    /// RST segment. It resets the connection if it is exactly at RCV.NXT,
+    /// anything else in the window gets a challenge ACK.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-3.2>
+    pub fn recv_rst(mut self, rst: &Rst) -> Reaction<'_, TcpClosed, Tcp<T>> {
+        let rst = self.parse(rst);
+        Reaction::from_inner(self.accept(&rst), self)
+    }
+
+    /// SYN once the connection is synchronized, other than the one we
+    /// answered in SYN-RECEIVED. The peer may have restarted, if so our
+    /// challenge ACK makes it send a reset with the right sequence number.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-4.2>
+    pub fn recv_syn(&mut self, syn: &Syn) -> Option<Ack> {
+        let syn = self.parse(syn);
+        match self.accept(&syn) {
+            ReactionInner::NotAcceptable(ack) => ack,
+            _ => unreachable!("a SYN is never accepted"),
+        }
+    }
+
+    /// Turn Nagle's algorithm off or back on.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.tcb.nodelay = nodelay;
+    }
+
+    /// Congestion window in bytes.
+    pub fn cwnd(&self) -> usize {
+        self.cc.cwnd() + self.tcb.cwnd_inflation
+    }
+
+    /// Slow start threshold in bytes.
+    pub fn ssthresh(&self) -> usize {
+        self.cc.ssthresh()
+    }
+
+    /// Time left until the retransmission timer fires, `None` if there is
+    /// nothing to retransmit.
+    pub fn retransmission_timeout(&self) -> Option<Duration> {
+        self.tcb.rto_deadline.map(time_left)
+    }
+
+    /// Time left until the retransmission or persist timer fires, `None` if
+    /// neither is running.
+    pub fn send_timeout(&self) -> Option<Duration> {
+        [self.tcb.rto_deadline, self.tcb.persist_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(time_left)
+    }
+
+    /// Time left until the first of the retransmission and delayed ACK
+    /// timers fires, `None` if neither is running.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        [
+            self.tcb.rto_deadline,
+            self.tcb.ack_deadline,
+            self.tcb.persist_deadline,
+            self.keepalive_deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(time_left)
+    }
+
+    /// Remember a sent segment so it can be retransmitted, and start the
+    /// retransmission timer and RTT measurement if they are not running.
+    fn queue_retransmission(&mut self, packet: &TcpPacket<Vec<u8>>) {
+        let now = Instant::now();
+        if self.tcb.rto_deadline.is_none() {
+            self.tcb.rto_deadline = Some(now + self.tcb.rtt.rto);
+        }
+        if self.tcb.rtt_probe.is_none() {
+            self.tcb.rtt_probe = Some((packet.seq_number() + packet.segment_len(), now));
+        }
+        self.retransmission.push(packet);
+    }
+
+    /// The retransmission timer fired. Back off and restart the timer, the RTT
+    /// of retransmitted segments must not be measured (Karn's algorithm).
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc6298#section-5>
+    fn retransmission_front(&mut self) -> Option<TcpPacket<Vec<u8>>> {
+        warn!("retransmission");
+        let packet = self.build_retransmission(self.tcb.snd_una, self.max_payload())?;
+        self.cc.on_rto(self.flight_size(), self.max_payload());
+        self.tcb.dup_acks = 0;
+        self.tcb.recover = None;
+        self.tcb.cwnd_inflation = 0;
+        self.tcb.retransmit_pending = false;
+        self.tcb.rtt.backoff();
+        self.tcb.rtt_probe = None;
+        self.tcb.rto_deadline = Some(Instant::now() + self.tcb.rtt.rto);
+        Some(packet)
+    }
+
+    /// The retransmission timer fired after our FIN went out, the oldest
+    /// unacknowledged segment may be data or the FIN itself.
+    fn retransmission_after_fin(&mut self) -> Option<Retransmission> {
+        let packet = self.retransmission_front()?;
+        Some(if packet.fin() {
+            Retransmission::Fin(FinAck::from_packet(packet))
+        } else {
+            Retransmission::Data(Ack::from_packet(packet))
+        })
+    }
+
+    /// The retransmission timer fired. Besides the segment at SND.UNA, resend
+    /// segments below the highest SACKed sequence number that the peer does
+    /// not hold, these are holes the peer is waiting for. The congestion
+    /// window limits how many of them go out.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc2018#section-5>
+    fn retransmission_holes(&mut self) -> Vec<TcpPacket<Vec<u8>>> {
+        let Some(front) = self.retransmission_front() else {
+            return Vec::new();
+        };
+        let mut budget = self.cwnd().saturating_sub(front.segment_len());
+        let mut seq = front.seq_number() + front.segment_len();
+        let mut packets = vec![front];
+        while let Some((sacked, sacked_end)) = self.scoreboard.block_after(seq) {
+            if sacked <= seq {
+                seq = sacked_end;
+                continue;
+            }
+            let len = (sacked - seq).min(self.max_payload());
+            if len > budget {
+                break;
+            }
+            let Some(packet) = self.build_retransmission(seq, len) else {
+                break;
+            };
+            budget -= packet.segment_len();
+            seq += packet.segment_len();
+            packets.push(packet);
+        }
+        packets
+    }
+
+    /// A segment resending up to `max_len` unacknowledged bytes from `seq`,
+    /// with the current acknowledgment number and window. The SYN goes out
+    /// exactly as before, it carries the handshake options.
+    fn build_retransmission(
+        &mut self,
+        seq: TcpSeqNumber,
+        max_len: usize,
+    ) -> Option<TcpPacket<Vec<u8>>> {
+        if let Some(syn) = self.retransmission.syn() {
+            return Some(self.restamp(syn.clone()));
+        }
+        let (payload, fin) = self.retransmission.segment(seq, max_len);
+        if payload.is_empty() && !fin {
+            return None;
+        }
+        Some(self.build_segment(seq, &payload, fin))
+    }
+
+    /// Bytes sent but not acknowledged yet.
+    fn flight_size(&self) -> usize {
+        self.tcb.snd_nxt - self.tcb.snd_una
+    }
+
+    /// `acked` bytes of new data were acknowledged. During fast recovery a
+    /// partial ACK retransmits the next segment, a full ACK ends recovery.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc6582#section-3.2>
+    fn on_new_ack(&mut self, ack_number: TcpSeqNumber, acked: usize) {
+        let mss = self.max_payload();
+        self.tcb.dup_acks = 0;
+        match self.tcb.recover {
+            Some(recover) if recover <= ack_number => {
+                self.tcb.recover = None;
+                self.tcb.cwnd_inflation = 0;
+                debug!("leaving fast recovery, cwnd {}", self.cwnd());
+            }
+            Some(_) => {
+                // partial ACK, deflate by the amount acknowledged
+                self.tcb.cwnd_inflation = self.tcb.cwnd_inflation.saturating_sub(acked);
+                if acked >= mss {
+                    self.tcb.cwnd_inflation += mss;
+                }
+                self.tcb.retransmit_pending = true;
+            }
+            None => self.cc.on_ack(acked, mss),
+        }
+    }
+
+    /// Fast retransmit after three duplicate ACKs, then inflate the window
+    /// for every further one as each means a segment has left the network.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5681#section-3.2>
+    fn on_dup_ack(&mut self) {
+        let mss = self.max_payload();
+        self.tcb.dup_acks += 1;
+        if self.tcb.recover.is_some() {
+            self.tcb.cwnd_inflation += mss;
+        } else if self.tcb.dup_acks == DUP_ACK_THRESHOLD {
+            self.cc.on_loss(self.flight_size(), mss);
+            self.tcb.cwnd_inflation = DUP_ACK_THRESHOLD as usize * mss;
+            self.tcb.recover = Some(self.tcb.snd_nxt);
+            self.tcb.retransmit_pending = true;
+            debug!(
+                "fast retransmit, cwnd {} ssthresh {}",
+                self.cwnd(),
+                self.ssthresh()
+            );
+        }
+    }
+
+    /// A pure ACK that acknowledges nothing new while data is outstanding.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5681#section-2>
+    fn is_dup_ack(&self, seg: &TcpRepr) -> bool {
+        seg.ack_number == Some(self.tcb.snd_una)
+            && seg.payload.is_empty()
+            && matches!(seg.control, TcpControl::None | TcpControl::Psh)
+            && self.peer_window(seg) == self.tcb.snd_wnd
+            && !self.retransmission.is_empty()
+    }
+
+    /// Refresh the timestamps of a segment about to be retransmitted, so the
+    /// peer's echo measures the RTT of this copy.
+    fn restamp(&self, packet: TcpPacket<Vec<u8>>) -> TcpPacket<Vec<u8>> {
+        let mut buf = packet.into_inner();
+        timestamps::restamp(
+            &mut buf,
+            Timestamp {
+                val: self.tcb.ts_val(),
+                ecr: self.tcb.ts_recent.map_or(0, |(recent, _)| recent),
+            },
+            &IpAddress::from(self.local.addr),
+            &IpAddress::from(self.remote.addr),
+            &self.local.checksum_caps,
+        );
+        TcpPacket::new_unchecked(buf)
+    }
+
+    /// The TSecr of a segment, if both sides use timestamps.
+    fn ts_ecr(&self, seg: &Segment) -> Option<u32> {
+        self.tcb.ts_recent.and(seg.timestamp).map(|ts| ts.ecr)
+    }
+
+    /// Record the SACK blocks of an incoming ACK. Blocks outside of the data
+    /// in flight are bogus or stale and ignored.
+    fn record_sack(&mut self, seg: &TcpRepr) {
+        if !self.tcb.sack_permitted {
+            return;
+        }
+        for &(left, right) in seg.sack_ranges.iter().flatten() {
+            let left = TcpSeqNumber(left as i32);
+            let right = TcpSeqNumber(right as i32);
+            if self.tcb.snd_una < left && right <= self.tcb.snd_nxt {
+                self.scoreboard.insert(left, right);
+            }
+        }
+    }
+
+    /// SACK blocks describing the out-of-order data we hold.
+    fn sack_ranges(&self) -> [Option<(u32, u32)>; 3] {
+        let mut ranges = [None; 3];
+        if self.tcb.sack_permitted {
+            for (range, (left, right)) in ranges.iter_mut().zip(self.reassembly.blocks()) {
+                *range = Some((left.0 as u32, right.0 as u32));
+            }
+        }
+        ranges
+    }
+
+    /// An ACK acknowledged new data up to `ack_number`. With timestamps,
+    /// every such ACK yields an RTT sample from its TSecr.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-4.1>
+    fn on_ack(&mut self, ack_number: TcpSeqNumber, ts_ecr: Option<u32>) {
+        let now = Instant::now();
+        match ts_ecr {
+            Some(ecr) => {
+                let rtt = self.tcb.ts_val().wrapping_sub(ecr);
+                if rtt as i32 >= 0 {
+                    self.tcb.rtt.sample(Duration::from_millis(u64::from(rtt)));
+                }
+                self.tcb.rtt_probe = None;
+            }
+            None => {
+                if let Some((end, sent_at)) = self.tcb.rtt_probe {
+                    if end <= ack_number {
+                        self.tcb.rtt.sample(now - sent_at);
+                        self.tcb.rtt_probe = None;
+                    }
+                }
+            }
+        }
+
+        self.scoreboard.acked(ack_number);
+
+        self.retransmission.ack(ack_number);
+
+        self.tcb.rto_deadline = if self.retransmission.is_empty() {
+            None
+        } else {
+            Some(now + self.tcb.rtt.rto)
+        };
+    }
+
     fn build_ack_raw(&mut self, payload: &[u8], fin: bool) -> TcpPacket<Vec<u8>> {
+        let len = payload.len() + usize::from(fin);
+        if len > self.usable_window() {
+            // Data goes through the send buffer and `transmit` never exceeds
+            // the window, so this only happens with a FIN at the very edge of
+            // the window.
+            warn!("Sending more than the send window allows");
+        }
+
+        let packet = self.build_segment(self.tcb.snd_nxt, payload, fin);
+        self.tcb.snd_nxt += len;
+        packet
+    }
+
+    /// A segment starting at `seq` carrying our current ACK and window.
+    fn build_segment(
+        &mut self,
+        seq: TcpSeqNumber,
+        payload: &[u8],
+        fin: bool,
+    ) -> TcpPacket<Vec<u8>> {
         let control = if fin {
             TcpControl::Fin
         } else if !payload.is_empty() {
@@ -256,28 +1202,28 @@ where
             src_port: self.local.port,
             dst_port: self.remote.port,
             control,
-            seq_number: self.tcb.snd_nxt,
+            seq_number: seq,
             ack_number: Some(self.tcb.rcv_nxt),
-            window_len: self.tcb.rcv_wnd,
+            window_len: self.advertised_window(),
             window_scale: None,
             max_seg_size: None,
             sack_permitted: false,
-            sack_ranges: [None, None, None],
+            sack_ranges: self.sack_ranges(),
             payload,
         };
 
-        if repr.segment_len() > self.tcb.snd_una + self.tcb.snd_wnd.into() - self.tcb.snd_nxt {
-            // This TCP implementation is fully synchronous, meaning that we
-            // do not buffer data to be sent. Becuase of that, implementing the
-            // (Sender's algorithm)[https://datatracker.ietf.org/doc/html/rfc9293#name-senders-algorithm-when-to-s]
-            // correctly is not quite possible.
-            //
-            // This situation is unlikely under our assumptions, however. So let's just
-            // warn the user and continue.
-            warn!("Sending more than the send window allows");
-        }
+        // Urgent data ahead, point at its end. Pointers that do not fit are
+        // capped, the peer still learns it is in urgent mode.
+        // Link: <https://datatracker.ietf.org/doc/html/rfc6093#section-4>
+        let urgent = self
+            .tcb
+            .snd_up
+            .filter(|up| *up > repr.seq_number)
+            .map(|up| (up - repr.seq_number).min(usize::from(u16::MAX)) as u16);
 
-        self.tcb.snd_nxt += repr.segment_len();
+        self.tcb.window_update_pending = false;
+        self.tcb.last_ack_sent = self.tcb.rcv_nxt;
+        self.tcb.ack_deadline = None;
 
         let mut buf = vec![0; repr.buffer_len()];
         let mut packet = TcpPacket::new_unchecked(&mut buf);
@@ -288,16 +1234,299 @@ where
             &IpAddress::from(self.remote.addr),
             &self.local.checksum_caps,
         );
+        if let Some(urgent) = urgent {
+            // TcpRepr has no urgent pointer, patch it in
+            packet.set_urg(true);
+            packet.set_urgent_at(urgent);
+            if self.local.checksum_caps.tcp.tx() {
+                packet.fill_checksum(
+                    &IpAddress::from(self.local.addr),
+                    &IpAddress::from(self.remote.addr),
+                );
+            }
+        }
+
+        if let Some((recent, _)) = self.tcb.ts_recent {
+            buf = timestamps::insert(
+                buf,
+                Timestamp {
+                    val: self.tcb.ts_val(),
+                    ecr: recent,
+                },
+                &IpAddress::from(self.local.addr),
+                &IpAddress::from(self.remote.addr),
+                &self.local.checksum_caps,
+            );
+        }
 
         TcpPacket::new_unchecked(buf)
     }
 
-    fn build_ack(&mut self, payload: &[u8]) -> Ack {
-        Ack::from_packet(self.build_ack_raw(payload, false))
+    /// Payload that fits into a segment next to the options [Self::build_segment]
+    /// puts in it right now, i.e. timestamps and SACK blocks for the
+    /// out-of-order data we hold.
+    fn max_payload(&self) -> usize {
+        let mut options = 0;
+        if self.tcb.ts_recent.is_some() {
+            options += timestamps::OPTION_LEN;
+        }
+        let blocks = self.sack_ranges().iter().flatten().count();
+        if blocks > 0 {
+            // kind, length and two sequence numbers per block, padded
+            // Link: <https://datatracker.ietf.org/doc/html/rfc2018#section-3>
+            options += (2 + 8 * blocks).next_multiple_of(4);
+        }
+        usize::from(self.tcb.snd_mss).saturating_sub(options).max(1)
+    }
+
+    /// The window field for an outgoing segment.
+    fn advertised_window(&self) -> u16 {
+        (self.tcb.rcv_wnd >> self.tcb.rcv_wnd_shift).min(u32::from(u16::MAX)) as u16
+    }
+
+    /// The window the peer advertised in a segment, after scaling.
+    fn peer_window(&self, seg: &TcpRepr) -> u32 {
+        u32::from(seg.window_len) << self.tcb.snd_wnd_shift
+    }
+
+    fn update_snd_wnd(&mut self, seg: &TcpRepr, ack_number: TcpSeqNumber) {
+        self.tcb.snd_wnd = self.peer_window(seg);
+        self.tcb.max_snd_wnd = self.tcb.max_snd_wnd.max(self.tcb.snd_wnd);
+        self.tcb.snd_wl1 = seg.seq_number;
+        self.tcb.snd_wl2 = ack_number;
+    }
+
+    /// How many more bytes the peer's window lets us send right now.
+    fn usable_window(&self) -> usize {
+        let wnd = (self.tcb.snd_wnd as usize).min(self.cwnd());
+        let wnd_end = self.tcb.snd_una + wnd;
+        if wnd_end > self.tcb.snd_nxt {
+            wnd_end - self.tcb.snd_nxt
+        } else {
+            0
+        }
+    }
+
+    /// Queue user data to be sent, returns how much of it fit into the send buffer.
+    fn buffer_data(&mut self, data: &[u8]) -> usize {
+        let free = self
+            .config
+            .send_buffer_size
+            .saturating_sub(self.send_buffer.len());
+        let accepted = data.len().min(free);
+        if accepted < data.len() {
+            warn!(
+                "send buffer full, accepted {} of {} bytes",
+                accepted,
+                data.len()
+            );
+        }
+        self.send_buffer.extend(&data[..accepted]);
+        accepted
+    }
+
+    /// Like [Self::buffer_data], and the urgent pointer moves to the end of it.
+    fn buffer_urgent_data(&mut self, data: &[u8]) -> usize {
+        let accepted = self.buffer_data(data);
+        if accepted > 0 {
+            self.tcb.snd_up = Some(self.tcb.snd_nxt + self.send_buffer.len());
+        }
+        accepted
+    }
+
+    /// Take all received data waiting for the user and open the receive window
+    /// for the space that frees up.
+    fn read_buffered(&mut self) -> Vec<u8> {
+        let data = self.recv_buffer.drain(..).collect();
+        self.open_window();
+        data
+    }
+
+    /// How many bytes at the front of the receive buffer are urgent, if the
+    /// peer moved its urgent pointer since the last call. The buffered data
+    /// ends right before `data_end`.
+    fn urgent_buffered(&mut self, data_end: TcpSeqNumber) -> Option<usize> {
+        if !std::mem::take(&mut self.tcb.urgent_pending) {
+            return None;
+        }
+        let rcv_up = self.tcb.rcv_up?;
+        let unread = data_end - self.recv_buffer.len();
+        if rcv_up <= unread {
+            return None;
+        }
+        Some((rcv_up - unread).min(self.recv_buffer.len()))
+    }
+
+    /// Sender's algorithm. Cut buffered data into segments of at most MSS bytes
+    /// while the peer's window has room for them.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.6.2.1>
+    fn transmit_buffered(&mut self) -> Segments {
+        let mut segments = Vec::new();
+        if self.tcb.retransmit_pending {
+            self.tcb.retransmit_pending = false;
+            if let Some(front) = self.build_retransmission(self.tcb.snd_una, self.max_payload()) {
+                warn!("fast retransmission");
+                segments.push(Ack::from_packet(front));
+                // Karn's algorithm
+                self.tcb.rtt_probe = None;
+            }
+        }
+        loop {
+            let len = self
+                .send_buffer
+                .len()
+                .min(self.max_payload())
+                .min(self.usable_window());
+            if len == 0 || self.nagle_holds(len) {
+                break;
+            }
+            let payload: Vec<u8> = self.send_buffer.drain(..len).collect();
+            let ack = self.build_ack(&payload);
+            self.queue_retransmission(ack.packet());
+            segments.push(ack);
+        }
+        if segments.is_empty() && self.tcb.window_update_pending {
+            segments.push(self.build_ack(&[]));
+        }
+        self.update_persist_timer();
+        Segments(segments)
+    }
+
+    /// Run the persist timer while data is waiting behind a zero window and
+    /// nothing is in flight, so no ACK is going to tell us the window opened.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.6.1>
+    fn update_persist_timer(&mut self) {
+        let stalled =
+            self.tcb.snd_wnd == 0 && !self.send_buffer.is_empty() && self.retransmission.is_empty();
+        if !stalled {
+            self.tcb.persist_deadline = None;
+            self.tcb.persist_backoff = 0;
+        } else if self.tcb.persist_deadline.is_none() {
+            let interval = (self.tcb.rtt.rto * (1 << self.tcb.persist_backoff.min(16)))
+                .min(PERSIST_MAX_INTERVAL);
+            self.tcb.persist_deadline = Some(Instant::now() + interval);
+        }
+    }
+
+    fn persist_expired(&self) -> bool {
+        self.tcb
+            .persist_deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+
+    /// When the next keepalive probe is due, or when the peer counts as gone
+    /// after the last probe went unanswered. Only idle connections are
+    /// probed, otherwise the retransmission or persist timer is running.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.4>
+    fn keepalive_deadline(&self) -> Option<Instant> {
+        let keepalive = self.config.keepalive?;
+        if !self.retransmission.is_empty() || self.tcb.persist_deadline.is_some() {
+            return None;
+        }
+        Some(self.tcb.last_recv + keepalive.idle + keepalive.interval * self.tcb.keepalive_probes)
+    }
+
+    fn keepalive_expired(&self) -> bool {
+        self.keepalive_deadline()
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+
+    /// All keepalive probes went unanswered.
+    fn peer_gone(&self) -> bool {
+        self.config
+            .keepalive
+            .is_some_and(|keepalive| self.tcb.keepalive_probes >= keepalive.probes)
+            && self.keepalive_expired()
+    }
+
+    /// A zero window or keepalive probe, an empty segment just below SND.NXT.
+    /// It falls outside the peer's window, so the peer answers with an ACK
+    /// carrying its current window.
+    fn build_probe(&mut self) -> Ack {
+        let snd_nxt = self.tcb.snd_nxt;
+        self.tcb.snd_nxt = snd_nxt - 1;
+        let probe = self.build_ack(&[]);
+        self.tcb.snd_nxt = snd_nxt;
+        probe
+    }
+
+    /// Nagle's algorithm. While data is unacknowledged, small segments wait
+    /// until a full one can be sent or everything has been acknowledged.
+    /// Once our user has closed nothing more is coming to fill them up.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.7.4>
+    fn nagle_holds(&self, len: usize) -> bool {
+        !self.tcb.nodelay
+            && !self.tcb.fin_pending
+            && self.tcb.snd_up.is_none()
+            && len < self.max_payload()
+            && self.flight_size() > 0
+    }
+
+    /// The user has read some data, see whether the window can be opened again.
+    /// To avoid the silly window syndrome, the window only opens once it can grow
+    /// by a reasonable amount, while the right edge never moves to the left.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.6.2.2>
+    fn open_window(&mut self) {
+        let free = self
+            .config
+            .recv_buffer_size
+            .saturating_sub(self.recv_buffer.len())
+            .min(max_wnd(self.tcb.rcv_wnd_shift));
+        let threshold = (self.config.recv_buffer_size / 2).min(usize::from(self.tcb.snd_mss));
+        if free >= self.tcb.rcv_wnd as usize + threshold {
+            debug!("opening receive window {} -> {}", self.tcb.rcv_wnd, free);
+            self.tcb.rcv_wnd = free as u32;
+            self.tcb.window_update_pending = true;
+        }
+    }
+
+    fn build_ack(&mut self, payload: &[u8]) -> Ack {
+        Ack::from_packet(self.build_ack_raw(payload, false))
     }
 
     fn build_fin(&mut self) -> FinAck {
-        FinAck::from_packet(self.build_ack_raw(&[], true))
+        let fin = self.build_ack_raw(&[], true);
+        self.queue_retransmission(&fin);
+        FinAck::from_packet(fin)
+    }
+
+    /// The FIN our user asked for in [Tcp::<Established>::close], now that
+    /// all data ahead of it has been sent. See [TcpForPicker::fin_due].
+    fn send_pending_fin(&mut self) -> FinAck {
+        debug_assert!(self.tcb.fin_pending && self.send_buffer.is_empty());
+        self.tcb.fin_pending = false;
+        self.build_fin()
+    }
+
+    /// The persist or keepalive timer fired, probe the peer. Zero window
+    /// probes back off exponentially.
+    fn probe_peer(&mut self) -> Ack {
+        if self.persist_expired() {
+            info!("probing zero window");
+            let probe = self.build_probe();
+            self.tcb.persist_backoff += 1;
+            self.tcb.persist_deadline = None;
+            self.update_persist_timer();
+            probe
+        } else {
+            self.tcb.keepalive_probes += 1;
+            info!("keepalive probe {}", self.tcb.keepalive_probes);
+            self.build_probe()
+        }
+    }
+
+    /// RST at SND.NXT for the user aborting the connection.
+    fn into_reset(self) -> (TcpClosed, Rst) {
+        if !self.send_buffer.is_empty() || !self.retransmission.is_empty() {
+            warn!("aborting with unacknowledged data");
+        }
+        let rst = self.build_reset(self.tcb.snd_nxt);
+        (self.transition(), rst)
     }
 
     fn build_reset(&self, seq: TcpSeqNumber) -> Rst {
@@ -307,7 +1536,7 @@ where
             control: TcpControl::Rst,
             seq_number: seq,
             ack_number: None,
-            window_len: self.tcb.rcv_wnd,
+            window_len: self.advertised_window(),
             window_scale: None,
             max_seg_size: None,
             sack_permitted: false,
@@ -328,141 +1557,382 @@ where
     }
 
     /// Determine if a segment's sequence number and length are acceptable
-    /// under the current receive window.
+    /// under the current receive window. Old duplicates carrying a timestamp
+    /// older than TS.Recent are rejected as well (PAWS).
     ///
     /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
-    fn is_seg_acceptable(&self, seg: &TcpRepr) -> bool {
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-5.3>
+    fn is_seg_acceptable(&self, seg: &Segment) -> bool {
+        if let (Some((_, updated)), Some(ts)) = (self.tcb.ts_recent, seg.timestamp) {
+            if seg.control != TcpControl::Rst
+                && !self.tcb.ts_not_older(ts)
+                && Instant::now() - updated < PAWS_IDLE
+            {
+                debug!("PAWS rejected a segment with TSval {}", ts.val);
+                return false;
+            }
+        }
+
         let seg_seq = seg.seq_number;
         let seg_len = seg.segment_len();
         let rcv_nxt = self.tcb.rcv_nxt;
-        let rcv_wnd = usize::from(self.tcb.rcv_wnd);
-
-        if seg.segment_len() == 0 {
-            if rcv_wnd == 0 {
-                seg_seq == rcv_nxt
-            } else {
-                rcv_nxt <= seg_seq && seg_seq < rcv_nxt + rcv_wnd
-            }
+        let rcv_wnd = self.tcb.rcv_wnd as usize;
+
+        if rcv_wnd == 0 {
+            // No text fits, but the ACK, URG and RST of a segment at RCV.NXT
+            // are still processed. Its text is dropped in [Self::accept].
+            seg_seq == rcv_nxt
+        } else if seg_len == 0 {
+            rcv_nxt <= seg_seq && seg_seq < rcv_nxt + rcv_wnd
         } else {
-            if self.tcb.rcv_wnd == 0 {
-                false
-            } else {
-                (rcv_nxt <= seg_seq && seg_seq < rcv_nxt + rcv_wnd)
-                    || (rcv_nxt <= seg_seq + seg_len - 1
-                        && seg_seq + seg_len - 1 < rcv_nxt + rcv_wnd)
+            (rcv_nxt <= seg_seq && seg_seq < rcv_nxt + rcv_wnd)
+                || (rcv_nxt <= seg_seq + seg_len - 1 && seg_seq + seg_len - 1 < rcv_nxt + rcv_wnd)
+        }
+    }
+
+    /// An ACK that is neither ahead of what we sent nor further behind
+    /// SND.UNA than the largest window the peer ever offered. Anything else
+    /// is likely a blind injection attempt.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-5.2>
+    fn is_ack_acceptable(&self, ack_number: TcpSeqNumber) -> bool {
+        let oldest = self.tcb.snd_una - self.tcb.max_snd_wnd as usize;
+        oldest <= ack_number && ack_number <= self.tcb.snd_nxt
+    }
+
+    /// An ACK telling the peer where we are, unless the challenge ACK budget
+    /// is used up.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-7>
+    fn challenge_ack(&mut self) -> Option<Ack> {
+        if !self.config.challenge_acks.allow(Instant::now()) {
+            debug!("challenge ACK rate limited");
+            return None;
+        }
+        Some(self.build_ack(&[]))
+    }
+
+    /// How a segment arriving in the SYN-SENT state is going to be received,
+    /// i.e. we are waiting for a SYN-ACK.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.3>
+    fn check_syn_sent(&self, seg: &Segment) -> Verdict {
+        let iss = self.tcb.snd_una;
+        let ack_acceptable = seg
+            .ack_number
+            .map(|ack_number| iss < ack_number && ack_number <= self.tcb.snd_nxt);
+
+        match (seg.control, ack_acceptable) {
+            // acceptable ACK, the connection was refused
+            (TcpControl::Rst, Some(true)) => Verdict::Reset,
+            (TcpControl::Rst, _) => Verdict::Ignore,
+            (_, Some(false)) => Verdict::BadAck,
+            // ignore Security
+            (TcpControl::Syn, Some(true)) => Verdict::InOrder,
+            // TODO simultaneous open, SYN without ACK should move us to SYN-RECEIVED
+            _ => Verdict::Ignore,
+        }
+    }
+
+    /// Segment arrives in the SYN-SENT state, i.e. we are waiting for a SYN-ACK.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.3>
+    fn accept_syn_sent<'a>(&mut self, seg: &Segment<'a>) -> ReactionInner<'a> {
+        let ack_number = match (self.check_syn_sent(seg), seg.ack_number) {
+            (Verdict::InOrder, Some(ack_number)) => ack_number,
+            (Verdict::Reset, _) => return ReactionInner::Reset(None),
+            // a bad ACK gets its RST from [Tcp::<SynSent>::reject_syn_ack]
+            _ => return ReactionInner::NotAcceptable(None),
+        };
+
+        // irs = seg.seq_number
+        self.tcb.rcv_nxt = seg.seq_number + 1;
+        self.tcb.snd_una = ack_number;
+        // the window in a SYN is never scaled
+        self.tcb.snd_wnd = u32::from(seg.window_len);
+        self.tcb.max_snd_wnd = self.tcb.snd_wnd;
+        match seg.window_scale {
+            Some(shift) => self.tcb.snd_wnd_shift = shift.min(MAX_WND_SHIFT),
+            None => {
+                // the peer does not scale, so neither can we
+                self.tcb.rcv_wnd_shift = 0;
+                self.tcb.rcv_wnd = self.tcb.rcv_wnd.min(u32::from(u16::MAX));
             }
         }
+        self.tcb.snd_wl1 = seg.seq_number;
+        self.tcb.snd_wl2 = ack_number;
+        self.tcb.snd_mss = self.local.effective_snd_mss(seg.max_seg_size);
+        self.tcb.sack_permitted = seg.sack_permitted;
+        self.tcb.ts_recent = seg.timestamp.map(|ts| (ts.val, Instant::now()));
+        self.cc = self.config.congestion.build(self.max_payload());
+        // the only thing in the queue is our SYN, which is now acknowledged
+        self.on_ack(ack_number, self.ts_ecr(seg));
+        self.tcb.last_recv = Instant::now();
+
+        // any data in the SYN-ACK is not accepted, rcv_nxt only covers the SYN
+        // so the peer will retransmit it.
+        ReactionInner::Acceptable(Some(self.build_ack(&[])), None)
     }
 
-    fn accept<'a>(&mut self, seg: &TcpRepr<'a>) -> ReactionInner<'a> {
+    /// How [Self::accept] is going to receive a segment. Nothing changes here,
+    /// so pickers can classify segments without side effects such as drawing
+    /// from the challenge ACK budget.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
+    fn check(&self, seg: &Segment) -> Verdict {
+        if TypeId::of::<T>() == TypeId::of::<SynSent>() {
+            return self.check_syn_sent(seg);
+        }
+
         if !self.is_seg_acceptable(seg) {
-            let reply = if seg.control == TcpControl::Rst {
-                None
-            } else {
-                Some(self.build_ack(&[]))
+            return Verdict::OutOfWindow;
+        }
+
+        match seg.control {
+            // clean reset
+            TcpControl::Rst if seg.seq_number == self.tcb.rcv_nxt => return Verdict::Reset,
+            // Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-3.2>
+            TcpControl::Rst => return Verdict::Challenge,
+            // ignore Security
+            // The peer may have restarted, if so our challenge ACK makes it
+            // send a reset with the right sequence number.
+            // Link: <https://datatracker.ietf.org/doc/html/rfc5961#section-4.2>
+            TcpControl::Syn => return Verdict::Challenge,
+            _ => {}
+        }
+
+        let Some(ack_number) = seg.ack_number else {
+            return Verdict::Ignore;
+        };
+
+        // SYN-RECEIVED STATE
+        if TypeId::of::<T>() == TypeId::of::<SynRcvd>()
+            && !(self.tcb.snd_una < ack_number && ack_number <= self.tcb.snd_nxt)
+        {
+            return Verdict::BadAck;
+        }
+
+        if !self.is_ack_acceptable(ack_number) {
+            return Verdict::Challenge;
+        }
+
+        if self.tcb.rcv_wnd == 0 && seg.segment_len() > 0 {
+            Verdict::WindowClosed
+        } else if seg.seq_number > self.tcb.rcv_nxt {
+            Verdict::OutOfOrder
+        } else {
+            Verdict::InOrder
+        }
+    }
+
+    fn accept<'a>(&mut self, seg: &Segment<'a>) -> ReactionInner<'a> {
+        if TypeId::of::<T>() == TypeId::of::<SynSent>() {
+            return self.accept_syn_sent(seg);
+        }
+
+        let verdict = self.check(seg);
+        if verdict == Verdict::OutOfWindow {
+            let reply = match seg.control {
+                TcpControl::Rst => None,
+                // a SYN gets a challenge ACK whatever its sequence number
+                TcpControl::Syn => self.challenge_ack(),
+                _ => Some(self.build_ack(&[])),
             };
             return ReactionInner::NotAcceptable(reply);
         }
 
-        if seg.seq_number > self.tcb.rcv_nxt {
-            warn!("gap before received segment, ignoring");
-            return ReactionInner::Acceptable(None, None);
+        // Only segments in the window show the peer is still there, anyone
+        // can send the others.
+        self.tcb.last_recv = Instant::now();
+        self.tcb.keepalive_probes = 0;
+
+        // Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-4.3>
+        if let Some(ts) = seg.timestamp {
+            if self.tcb.ts_recent.is_some()
+                && self.tcb.ts_not_older(ts)
+                && seg.seq_number <= self.tcb.last_ack_sent
+            {
+                self.tcb.ts_recent = Some((ts.val, Instant::now()));
+            }
         }
 
-        let payload = seg
-            .payload
-            .get(self.tcb.rcv_nxt - seg.seq_number..)
-            .unwrap_or(&[]);
-        // let's not worry about payloads that are too long
+        let ack_number = match (verdict, seg.ack_number) {
+            (Verdict::Reset, _) => return ReactionInner::Reset(None),
+            (Verdict::Challenge, _) => return ReactionInner::NotAcceptable(self.challenge_ack()),
+            (Verdict::BadAck, Some(ack_number)) => {
+                return ReactionInner::Reset(Some(self.build_reset(ack_number)))
+            }
+            (Verdict::WindowClosed | Verdict::OutOfOrder | Verdict::InOrder, Some(ack_number)) => {
+                ack_number
+            }
+            _ => return ReactionInner::NotAcceptable(None),
+        };
+
+        if TypeId::of::<T>() == TypeId::of::<SynRcvd>() {
+            self.update_snd_wnd(seg, ack_number);
+        }
 
-        if seg.control == TcpControl::Rst {
-            if seg.seq_number == self.tcb.rcv_nxt {
-                // clean reset
-                return ReactionInner::Reset(None);
+        if self.tcb.snd_una < ack_number && ack_number <= self.tcb.snd_nxt {
+            let acked = ack_number - self.tcb.snd_una;
+            self.tcb.snd_una = ack_number;
+            if self.tcb.snd_up.is_some_and(|up| up <= ack_number) {
+                self.tcb.snd_up = None;
             }
-            // challenge ACK
+            self.on_ack(ack_number, self.ts_ecr(seg));
+            self.on_new_ack(ack_number, acked);
+        } else if self.is_dup_ack(seg) {
+            self.on_dup_ack();
+        }
+        self.record_sack(seg);
+
+        // SND.UNA =< SEG.ACK =< SND.NXT
+        if self.tcb.snd_una <= ack_number && ack_number <= self.tcb.snd_nxt {
+            if self.tcb.snd_wl1 < seg.seq_number
+                || (self.tcb.snd_wl1 == seg.seq_number && self.tcb.snd_wl2 <= ack_number)
+            {
+                self.update_snd_wnd(seg, ack_number);
+            }
+        }
+        self.update_persist_timer();
+
+        // Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.5>
+        if let Some(urgent) = seg.urgent {
+            self.record_urgent(seg.seq_number + usize::from(urgent));
+        }
+
+        if verdict == Verdict::WindowClosed {
+            debug!(
+                "receive window closed, dropping {} bytes",
+                seg.payload.len()
+            );
             return ReactionInner::NotAcceptable(Some(self.build_ack(&[])));
         }
 
-        // ignore Security
+        // Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
+        if !seg.payload.is_empty()
+            && (TypeId::of::<T>() == TypeId::of::<CloseWait>()
+                || TypeId::of::<T>() == TypeId::of::<Closing>()
+                || TypeId::of::<T>() == TypeId::of::<LastAck>()
+                || TypeId::of::<T>() == TypeId::of::<TimeWait>())
+        {
+            debug!("ignoring {} bytes after the peer's FIN", seg.payload.len());
+            return ReactionInner::Acceptable(Some(self.build_ack(&[])), None);
+        }
 
-        if seg.control == TcpControl::Syn {
-            // TODO not sure if this is right
+        if verdict == Verdict::OutOfOrder {
+            // There is a hole in front of this segment. Hold on to the data
+            // (but not a FIN) until it is filled and ACK what we have so far.
+            let wnd_end = self.tcb.rcv_nxt + self.tcb.rcv_wnd as usize;
+            let len = seg.payload.len().min(wnd_end - seg.seq_number);
+            self.reassembly.insert(seg.seq_number, &seg.payload[..len]);
+            debug!("holding {} bytes out of order", self.reassembly.len());
             return ReactionInner::NotAcceptable(Some(self.build_ack(&[])));
         }
 
-        match seg.ack_number {
-            Some(ack_number) => {
-                // TODO RFC 5961
+        let mut payload = Cow::Borrowed(
+            seg.payload
+                .get(self.tcb.rcv_nxt - seg.seq_number..)
+                .unwrap_or(&[]),
+        );
+        // let's not worry about payloads that are too long
 
-                // SYN-RECEIVED STATE
-                if TypeId::of::<T>() == TypeId::of::<SynRcvd>() {
-                    if self.tcb.snd_una < ack_number && ack_number <= self.tcb.snd_nxt {
-                        // TODO RFC 5961
-                        self.tcb.snd_wnd = seg.window_len;
-                        self.tcb.snd_wl1 = seg.seq_number;
-                        self.tcb.snd_wl2 = ack_number;
-                    } else {
-                        return ReactionInner::Reset(Some(self.build_reset(ack_number)));
-                    }
-                }
+        let ack_delay = self.ack_delay(seg);
+        self.tcb.rcv_nxt = seg.seq_number + seg.segment_len();
+        if seg.control != TcpControl::Fin {
+            if let Some(held) = self.reassembly.pop(self.tcb.rcv_nxt) {
+                self.tcb.rcv_nxt += held.len();
+                payload.to_mut().extend_from_slice(&held);
+            }
+        }
 
-                if self.tcb.snd_una < ack_number && ack_number <= self.tcb.snd_nxt {
-                    self.tcb.snd_una = ack_number;
-                    // pop all acknowledged segments from the retransmission queue
-                    while let Some(rt) = self.retransmission.front() {
-                        if rt.seq_number() + rt.segment_len() <= ack_number {
-                            self.retransmission.pop_front();
-                        } else {
-                            break;
-                        }
-                    }
-                } else if ack_number > self.tcb.snd_nxt {
-                    return ReactionInner::NotAcceptable(Some(self.build_ack(&[])));
-                }
+        // The data now waits for the user, keep the right edge of the window in place.
+        self.recv_buffer.extend(payload.iter());
+        self.tcb.rcv_wnd = self
+            .tcb
+            .rcv_wnd
+            .saturating_sub(payload.len().min(u32::MAX as usize) as u32);
+
+        let ack = match ack_delay {
+            _ if seg.segment_len() == 0 => None,
+            Some(delay) => {
+                self.tcb.ack_deadline = Some(Instant::now() + delay);
+                None
+            }
+            None => Some(self.build_ack(&[])),
+        };
+        ReactionInner::Acceptable(
+            ack,
+            if payload.is_empty() {
+                None
+            } else {
+                Some(payload)
+            },
+        )
+    }
 
-                // SND.UNA =< SEG.ACK =< SND.NXT
-                if self.tcb.snd_una <= ack_number && ack_number <= self.tcb.snd_nxt {
-                    if self.tcb.snd_wl1 < seg.seq_number
-                        || (self.tcb.snd_wl1 == seg.seq_number && self.tcb.snd_wl2 <= ack_number)
-                    {
-                        self.tcb.snd_wnd = seg.window_len;
-                        self.tcb.snd_wl1 = seg.seq_number;
-                        self.tcb.snd_wl2 = ack_number;
-                    }
-                }
+    /// The peer's urgent pointer, only taken into account while the user is
+    /// still reading. It never moves backwards.
+    fn record_urgent(&mut self, rcv_up: TcpSeqNumber) {
+        if TypeId::of::<T>() != TypeId::of::<Established>()
+            && TypeId::of::<T>() != TypeId::of::<FinWait1>()
+            && TypeId::of::<T>() != TypeId::of::<FinWait2>()
+        {
+            return;
+        }
+        if self.tcb.rcv_up.is_none_or(|up| up < rcv_up) {
+            debug!("urgent data up to {}", rcv_up);
+            self.tcb.rcv_up = Some(rcv_up);
+            self.tcb.urgent_pending = true;
+        }
+    }
+
+    /// How long the ACK for an in-order segment about to be accepted can wait
+    /// for outgoing data, `None` if it goes out right away. Every second
+    /// segment is acknowledged right away, as are FINs and segments around a
+    /// hole in the sequence space.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.8.6.3>
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc5681#section-4.2>
+    fn ack_delay(&self, seg: &TcpRepr) -> Option<Duration> {
+        let delay = self.config.delayed_ack?;
+        let delayed = TypeId::of::<T>() == TypeId::of::<Established>()
+            && seg.control != TcpControl::Fin
+            && self.reassembly.is_empty()
+            && self.tcb.ack_deadline.is_none();
+        delayed.then_some(delay)
+    }
 
-                // ignore URG
-
-                self.tcb.rcv_nxt = seg.seq_number + seg.segment_len();
-                ReactionInner::Acceptable(
-                    if seg.segment_len() > 0 {
-                        Some(self.build_ack(&[]))
-                    } else {
-                        None
-                    },
-                    if payload.len() > 0 {
-                        Some(payload)
-                    } else {
-                        None
-                    },
-                )
+    fn reack_fin(&mut self, fin: &FinAck) -> Ack {
+        let fin = self.parse(fin);
+        match self.accept(&fin) {
+            ReactionInner::Acceptable(Some(ack), _) | ReactionInner::NotAcceptable(Some(ack)) => {
+                ack
             }
-            None => ReactionInner::NotAcceptable(None),
+            _ => self.build_ack(&[]),
         }
     }
 
-    fn parse<'a, M>(&self, packet: &'a M) -> TcpRepr<'a>
+    fn parse<'a, M>(&self, packet: &'a M) -> Segment<'a>
     where
         M: SmolMessage,
     {
-        TcpRepr::parse(
-            &TcpPacket::new_unchecked(packet.packet().as_ref()),
+        self.parse_raw(packet.packet().as_ref())
+    }
+
+    fn parse_raw<'a>(&self, segment: &'a [u8]) -> Segment<'a> {
+        let repr = TcpRepr::parse(
+            &TcpPacket::new_unchecked(segment),
             &IpAddress::from(self.remote.addr),
             &IpAddress::from(self.local.addr),
             &self.local.checksum_caps,
         )
-        .unwrap()
+        .unwrap();
+        let packet = TcpPacket::new_unchecked(segment);
+        Segment {
+            repr,
+            timestamp: timestamps::parse(segment),
+            urgent: packet.urg().then(|| packet.urgent_at()),
+        }
     }
 
     pub fn for_picker(&self) -> TcpForPicker<T> {
@@ -491,6 +1961,12 @@ where
             remote: self.remote,
             tcb: self.tcb,
             retransmission: self.retransmission,
+            scoreboard: self.scoreboard,
+            cc: self.cc,
+            send_buffer: self.send_buffer,
+            reassembly: self.reassembly,
+            recv_buffer: self.recv_buffer,
+            config: self.config,
             _marker: PhantomData,
         }
     }
@@ -498,7 +1974,48 @@ where
 
 impl<T> Transition<TcpClosed> for Tcp<T> {
     fn transition(self) -> TcpClosed {
-        TcpClosed {}
+        TcpClosed {
+            config: self.config,
+        }
+    }
+}
+
+impl Tcp<SynSent> {
+    pub fn recv_syn_ack(
+        mut self,
+        syn_ack: &SynAck,
+    ) -> Reaction<'_, Tcp<Established>, Tcp<SynSent>> {
+        let syn_ack = self.parse(syn_ack);
+        Reaction::from_inner(self.accept(&syn_ack), self)
+    }
+
+    /// SYN-ACK which acknowledges something other than our SYN, see
+    /// [TcpForPicker::bad_ack]. It gets a RST and we keep waiting for the
+    /// right one.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.3>
+    pub fn reject_syn_ack(&self, syn_ack: &SynAck) -> Rst {
+        let syn_ack = self.parse(syn_ack);
+        debug_assert_eq!(self.check_syn_sent(&syn_ack), Verdict::BadAck);
+        self.build_reset(syn_ack.ack_number.expect("SYN-ACK without ACK"))
+    }
+
+    pub fn retransmission(&mut self) -> Option<Syn> {
+        self.retransmission_front().map(Syn::from_packet)
+    }
+
+    /// RST which acknowledges our SYN, see [TcpForPicker::refused]. The peer
+    /// refused the connection.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.3>
+    pub fn recv_reset(mut self, rst: &Rst) -> TcpClosed {
+        let rst = self.parse(rst);
+        let reaction = self.accept(&rst);
+        assert!(
+            matches!(reaction, ReactionInner::Reset(None)),
+            "RST acknowledging our SYN must reset"
+        );
+        self.transition()
     }
 }
 
@@ -507,6 +2024,34 @@ impl Tcp<SynRcvd> {
         let ack = self.parse(ack);
         Reaction::from_inner(self.accept(&ack), self)
     }
+
+    pub fn retransmission(&mut self) -> Option<SynAck> {
+        self.retransmission_front().map(SynAck::from_packet)
+    }
+
+    /// The peer retransmitted the SYN we answered, so our SYN-ACK was lost.
+    /// It goes out again right away, the retransmission timer is left alone.
+    pub fn recv_retransmitted_syn(&mut self, _syn: &Syn) -> SynAck {
+        let syn_ack = self.retransmission.syn().expect("SYN-ACK not queued");
+        SynAck::from_packet(self.restamp(syn_ack.clone()))
+    }
+
+    /// RST exactly at RCV.NXT. This connection came from a passive open, so
+    /// instead of closing it goes back to listening.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
+    pub fn recv_reset(mut self, rst: &Rst) -> TcpListen {
+        let rst = self.parse(rst);
+        let reaction = self.accept(&rst);
+        assert!(
+            matches!(reaction, ReactionInner::Reset(None)),
+            "RST at RCV.NXT must reset"
+        );
+        TcpListen {
+            local: self.local,
+            config: self.config,
+        }
+    }
 }
 
 impl Tcp<Established> {
@@ -520,107 +2065,1913 @@ impl Tcp<Established> {
         Reaction::from_inner(self.accept(&fin), self)
     }
 
-    pub fn send(&mut self, data: &[u8]) -> Ack {
-        let ack = self.build_ack(data);
-        self.retransmission.push_back(ack.packet().clone());
-        ack
+    /// Take all received data waiting for the user. This frees up space in the
+    /// receive window, the resulting window update goes out with [Self::transmit].
+    pub fn read(&mut self) -> Vec<u8> {
+        self.read_buffered()
     }
 
-    pub fn close(mut self) -> (Tcp<FinWait1>, FinAck) {
-        let fin = self.build_fin();
-        (self.transition(), fin)
+    /// How many bytes at the front of what [Self::read] returns next are
+    /// urgent, if the peer moved its urgent pointer since the last call.
+    pub fn urgent(&mut self) -> Option<usize> {
+        self.urgent_buffered(self.tcb.rcv_nxt)
     }
 
-    pub fn retransmission(&self) -> Option<Ack> {
-        warn!("retransmission");
-        self.retransmission
-            .get(0)
-            .map(|p| Ack::from_packet(p.clone()))
+    /// Queue user data, returns how many bytes were accepted. Anything short
+    /// of `data.len()` means the send buffer is full.
+    pub fn send(&mut self, data: &[u8]) -> usize {
+        self.buffer_data(data)
+    }
+
+    /// Like [Self::send], but the data is urgent. The peer is told where it
+    /// ends with every segment until it is acknowledged, and it is not held
+    /// back by Nagle's algorithm.
+    pub fn send_urgent(&mut self, data: &[u8]) -> usize {
+        self.buffer_urgent_data(data)
+    }
+
+    /// Segments of buffered data the peer's window has room for now,
+    /// or a window update if there is no data to carry it.
+    pub fn transmit(&mut self) -> Segments {
+        self.transmit_buffered()
+    }
+
+    /// Close our side. Data still waiting in the send buffer keeps going out
+    /// as the windows allow, the FIN follows it from FIN-WAIT-1 once the
+    /// buffer is empty, see [Tcp::<FinWait1>::send_fin].
+    pub fn close(mut self) -> (Tcp<FinWait1>, Segments) {
+        self.tcb.fin_pending = true;
+        let segments = self.transmit_buffered();
+        (self.transition(), segments)
+    }
+
+    /// Reset the connection instead of closing it, like closing with
+    /// `SO_LINGER` set to zero. Buffered data is discarded.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.5>
+    pub fn abort(self) -> (TcpClosed, Rst) {
+        self.into_reset()
+    }
+
+    /// Current retransmission timeout.
+    pub fn rto(&self) -> Duration {
+        self.tcb.rtt.rto
+    }
+
+    /// The retransmission timer fired, resend what the peer is missing.
+    pub fn retransmission(&mut self) -> Segments {
+        Segments(
+            self.retransmission_holes()
+                .into_iter()
+                .map(Ack::from_packet)
+                .collect(),
+        )
+    }
+
+    /// One of the timers fired. Resends what the peer is missing if it was
+    /// the retransmission timer, and sends a delayed ACK that is due.
+    pub fn timeout(&mut self) -> Segments {
+        let now = Instant::now();
+        let mut segments = if self.tcb.rto_deadline.is_some_and(|d| d <= now) {
+            self.retransmission()
+        } else {
+            Segments(Vec::new())
+        };
+        if self.tcb.ack_deadline.is_some_and(|d| d <= now) {
+            segments.0.push(self.build_ack(&[]));
+        }
+        segments
+    }
+
+    /// The persist or keepalive timer fired, probe the peer. Zero window
+    /// probes back off exponentially.
+    pub fn probe(&mut self) -> Ack {
+        self.probe_peer()
     }
 }
 
 impl Tcp<FinWait1> {
-    pub fn recv_fin(mut self, fin: &FinAck) -> Reaction<TcpClosed, Tcp<FinWait1>> {
+    /// FIN which also acknowledges our FIN. Any data it carries is
+    /// acknowledged and dropped, our user has closed.
+    pub fn recv_fin(mut self, fin: &FinAck) -> Reaction<'_, Tcp<TimeWait>, Tcp<FinWait1>> {
         let fin = self.parse(fin);
-        Reaction::from_inner(self.accept(&fin), self)
+        let reaction = self.accept(&fin);
+        self.recv_buffer.clear();
+        self.open_window();
+        Reaction::from_inner(reaction, self)
+    }
+
+    /// FIN which does not acknowledge our FIN yet, i.e. both sides
+    /// are closing at the same time. Any data it carries is dropped as well.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.6>
+    pub fn recv_simultaneous_fin(
+        mut self,
+        fin: &FinAck,
+    ) -> Reaction<'_, Tcp<Closing>, Tcp<FinWait1>> {
+        let fin = self.parse(fin);
+        let reaction = self.accept(&fin);
+        self.recv_buffer.clear();
+        self.open_window();
+        Reaction::from_inner(reaction, self)
     }
 
+    /// ACK of our FIN. Any data it carries is acknowledged and dropped.
     pub fn recv_ack(mut self, ack: &Ack) -> Reaction<Tcp<FinWait2>, Tcp<FinWait1>> {
         let ack = self.parse(ack);
-        Reaction::from_inner(self.accept(&ack), self)
+        let reaction = self.accept(&ack);
+        self.recv_buffer.clear();
+        self.open_window();
+        Reaction::from_inner(reaction, self)
+    }
+
+    /// ACK that does not cover our FIN yet. Any data it carries is
+    /// acknowledged and dropped, our user has closed. Buffered data the
+    /// windows now have room for goes out along with the ACK.
+    pub fn recv_ack_of_data(&mut self, ack: &Ack) -> Segments {
+        let ack = self.parse(ack);
+        let reaction = self.accept(&ack);
+        self.recv_buffer.clear();
+        self.open_window();
+        let mut segments = match reaction {
+            ReactionInner::Acceptable(ack, _) | ReactionInner::NotAcceptable(ack) => {
+                Segments(ack.into_iter().collect())
+            }
+            ReactionInner::Reset(_) => unreachable!("reset without RST"),
+        };
+        segments.0.extend(self.transmit_buffered().0);
+        segments
+    }
+
+    /// The send buffer is empty, our FIN goes out.
+    pub fn send_fin(&mut self) -> FinAck {
+        self.send_pending_fin()
+    }
+
+    /// The persist timer fired while data is waiting to go out ahead of our FIN.
+    pub fn probe(&mut self) -> Ack {
+        self.probe_peer()
+    }
+
+    pub fn retransmission(&mut self) -> Option<Retransmission> {
+        self.retransmission_after_fin()
     }
 }
 
 impl Tcp<FinWait2> {
-    pub fn recv_ack(&mut self, ack: &Ack) -> Ack {
+    /// Any data it carries is acknowledged and dropped, our user has closed.
+    pub fn recv_ack(mut self, ack: &Ack) -> Reaction<'_, Tcp<FinWait2>, Tcp<FinWait2>> {
         let ack = self.parse(ack);
-        match self.accept(&ack) {
-            ReactionInner::Acceptable(Some(ack), _) => ack,
-            ReactionInner::Acceptable(None, _) => todo!(),
-            ReactionInner::NotAcceptable(_) => todo!(),
-            ReactionInner::Reset(_) => todo!(),
-        }
+        let reaction = self.accept(&ack);
+        // our user has closed, nobody is going to read this
+        self.recv_buffer.clear();
+        self.open_window();
+        Reaction::from_inner(reaction, self)
     }
 
-    pub fn recv_fin(mut self, fin: &FinAck) -> Ack {
+    pub fn recv_fin(mut self, fin: &FinAck) -> Reaction<'_, Tcp<TimeWait>, Tcp<FinWait2>> {
         let fin = self.parse(fin);
-        match self.accept(&fin) {
-            ReactionInner::Acceptable(Some(ack), _) => ack,
-            ReactionInner::Acceptable(None, _) => todo!(),
-            ReactionInner::NotAcceptable(_) => todo!(),
-            ReactionInner::Reset(_) => todo!(),
-        }
+        let reaction = self.accept(&fin);
+        self.recv_buffer.clear();
+        self.open_window();
+        Reaction::from_inner(reaction, self)
     }
 }
 
-impl Tcp<CloseWait> {
-    pub fn recv_ack(&mut self, ack: &Ack) {
+impl Tcp<Closing> {
+    /// ACK of our FIN. Anything else it carries is acknowledged and dropped,
+    /// the peer has sent its FIN already.
+    pub fn recv_ack(mut self, ack: &Ack) -> Reaction<'_, Tcp<TimeWait>, Tcp<Closing>> {
         let ack = self.parse(ack);
-        match self.accept(&ack) {
-            ReactionInner::Acceptable(None, None) => {}
-            ReactionInner::Acceptable(_, _) => todo!(),
-            ReactionInner::NotAcceptable(_) => todo!(),
-            ReactionInner::Reset(_) => todo!(),
-        }
+        Reaction::from_inner(self.accept(&ack), self)
     }
 
-    pub fn send(&mut self, data: &[u8]) -> Ack {
-        self.build_ack(data)
+    /// The peer retransmitted its FIN, so our ACK of it was lost. ACK it again.
+    pub fn recv_fin(&mut self, fin: &FinAck) -> Ack {
+        self.reack_fin(fin)
     }
 
-    pub fn close(mut self) -> (Tcp<LastAck>, FinAck) {
-        let fin = self.build_fin();
-        (self.transition(), fin)
+    /// ACK of data sent ahead of our FIN, or any segment that is not
+    /// acceptable. Buffered data the windows now have room for goes out along
+    /// with the ACK, if any.
+    pub fn recv_ack_of_data(&mut self, ack: &Ack) -> Segments {
+        let ack = self.parse(ack);
+        let mut segments = match self.accept(&ack) {
+            ReactionInner::Acceptable(ack, _) | ReactionInner::NotAcceptable(ack) => {
+                Segments(ack.into_iter().collect())
+            }
+            ReactionInner::Reset(_) => unreachable!("reset without RST"),
+        };
+        segments.0.extend(self.transmit_buffered().0);
+        segments
+    }
+
+    /// The send buffer is empty, our FIN goes out.
+    pub fn send_fin(&mut self) -> FinAck {
+        self.send_pending_fin()
+    }
+
+    /// The persist timer fired while data is waiting to go out ahead of our FIN.
+    pub fn probe(&mut self) -> Ack {
+        self.probe_peer()
+    }
+
+    pub fn retransmission(&mut self) -> Option<Retransmission> {
+        self.retransmission_after_fin()
     }
 }
 
-impl Tcp<LastAck> {
-    pub fn recv_ack(mut self, ack: &Ack) {
-        let ack = self.parse(ack);
-        match self.accept(&ack) {
-            ReactionInner::Acceptable(None, None) => {}
-            ReactionInner::Acceptable(_, _) => todo!(),
-            ReactionInner::NotAcceptable(_) => todo!(),
-            ReactionInner::Reset(_) => todo!(),
-        }
+impl Tcp<TimeWait> {
+    /// How much longer to linger before the connection is finally closed,
+    /// 2*MSL from the first call. The timer is restarted by every
+    /// retransmitted FIN, and nothing else.
+    ///
+    /// Link: <https://datatracker.ietf.org/doc/html/rfc9293#section-3.10.7.4>
+    pub fn timeout(&mut self) -> Duration {
+        let linger = self.config.msl * 2;
+        let deadline = self
+            .tcb
+            .time_wait_deadline
+            .get_or_insert_with(|| Instant::now() + linger);
+        time_left(*deadline)
+    }
+
+    /// The peer retransmitted its FIN, so our ACK of it was lost. ACK it again.
+    pub fn recv_fin(&mut self, fin: &FinAck) -> Ack {
+        self.tcb.time_wait_deadline = Some(Instant::now() + self.config.msl * 2);
+        self.reack_fin(fin)
     }
 }
 
-impl<T> TcpForPicker<T>
-where
+impl Tcp<CloseWait> {
+    pub fn recv_ack(mut self, ack: &Ack) -> Reaction<'_, Tcp<CloseWait>, Tcp<CloseWait>> {
+        let ack = self.parse(ack);
+        Reaction::from_inner(self.accept(&ack), self)
+    }
+
+    /// Take the data that came with the peer's FIN. Nothing more follows it.
+    pub fn read(&mut self) -> Vec<u8> {
+        self.read_buffered()
+    }
+
+    /// How many bytes at the front of what [Self::read] returns are urgent,
+    /// if the peer moved its urgent pointer before its FIN.
+    pub fn urgent(&mut self) -> Option<usize> {
+        // RCV.NXT is past the FIN
+        self.urgent_buffered(self.tcb.rcv_nxt - 1)
+    }
+
+    /// Queue user data, returns how many bytes were accepted. Anything short
+    /// of `data.len()` means the send buffer is full.
+    pub fn send(&mut self, data: &[u8]) -> usize {
+        self.buffer_data(data)
+    }
+
+    /// Like [Self::send], but the data is urgent.
+    pub fn send_urgent(&mut self, data: &[u8]) -> usize {
+        self.buffer_urgent_data(data)
+    }
+
+    /// Segments of buffered data the peer's window has room for now.
+    pub fn transmit(&mut self) -> Segments {
+        self.transmit_buffered()
+    }
+
+    /// The peer retransmitted its FIN, so our ACK of it was lost. ACK it again.
+    pub fn recv_fin(&mut self, fin: &FinAck) -> Ack {
+        self.reack_fin(fin)
+    }
+
+    /// The persist or keepalive timer fired, probe the peer.
+    pub fn probe(&mut self) -> Ack {
+        self.probe_peer()
+    }
+
+    /// The retransmission timer fired, resend what the peer is missing.
+    pub fn retransmission(&mut self) -> Segments {
+        Segments(
+            self.retransmission_holes()
+                .into_iter()
+                .map(Ack::from_packet)
+                .collect(),
+        )
+    }
+
+    /// Close our side. Our user only gets here once everything it sent has
+    /// been acknowledged, see [Tcp::is_drained].
+    pub fn close(mut self) -> (Tcp<LastAck>, FinAck) {
+        debug_assert!(self.send_buffer.is_empty());
+        let fin = self.build_fin();
+        (self.transition(), fin)
+    }
+
+    /// Reset the connection instead of closing it. Buffered data is discarded.
+    pub fn abort(self) -> (TcpClosed, Rst) {
+        self.into_reset()
+    }
+}
+
+impl Tcp<LastAck> {
+    /// ACK of our FIN.
+    pub fn recv_ack(mut self, ack: &Ack) -> Reaction<'_, TcpClosed, Tcp<LastAck>> {
+        let ack = self.parse(ack);
+        Reaction::from_inner(self.accept(&ack), self)
+    }
+
+    /// ACK that does not cover our FIN, e.g. an old duplicate, or any
+    /// segment that is not acceptable.
+    pub fn recv_ack_of_data(&mut self, ack: &Ack) -> Segments {
+        let ack = self.parse(ack);
+        match self.accept(&ack) {
+            ReactionInner::Acceptable(ack, _) | ReactionInner::NotAcceptable(ack) => {
+                Segments(ack.into_iter().collect())
+            }
+            ReactionInner::Reset(_) => unreachable!("reset without RST"),
+        }
+    }
+
+    /// The peer retransmitted its FIN, so our ACK of it was lost. ACK it again.
+    pub fn recv_fin(&mut self, fin: &FinAck) -> Ack {
+        self.reack_fin(fin)
+    }
+
+    pub fn retransmission(&mut self) -> Option<FinAck> {
+        self.retransmission_front().map(FinAck::from_packet)
+    }
+}
+
+impl<T> TcpForPicker<T>
+where
     T: TcpState + 'static,
 {
-    pub fn acceptable<'a, U>(mut self, packet: &'a TcpPacket<U>) -> ReactionInner
+    /// Whether `packet` acknowledges everything we have sent, including our FIN.
+    pub fn acks_fin<U>(&self, packet: &TcpPacket<U>) -> bool
     where
         U: AsRef<[u8]>,
     {
-        let packet = TcpRepr::parse(
+        !self.0.tcb.fin_pending && packet.ack() && packet.ack_number() == self.0.tcb.snd_nxt
+    }
+
+    /// Whether our user has closed and all data ahead of the FIN has been
+    /// sent, so the FIN can go out.
+    pub fn fin_due(&self) -> bool {
+        self.0.tcb.fin_pending && self.0.send_buffer.is_empty()
+    }
+
+    /// Whether `packet` is a RST exactly at RCV.NXT, which resets the connection.
+    /// Any other RST is answered with a challenge ACK.
+    pub fn resets<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        packet.rst() && packet.seq_number() == self.0.tcb.rcv_nxt
+    }
+
+    /// Whether `packet` is a RST which acknowledges our SYN, i.e. the peer
+    /// refused the connection. Any other RST in SYN-SENT is dropped.
+    pub fn refused<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        let segment = self.0.parse_raw(packet.as_ref());
+        packet.rst() && self.0.check(&segment) == Verdict::Reset
+    }
+
+    /// Whether the persist or keepalive timer is the one that expired.
+    pub fn probe_due(&self) -> bool {
+        self.0.persist_expired() || self.0.keepalive_expired()
+    }
+
+    /// Whether the keepalive probes went unanswered.
+    pub fn peer_gone(&self) -> bool {
+        self.0.peer_gone()
+    }
+
+    /// Whether `packet` is the SYN we already answered, sent again.
+    pub fn retransmitted_syn<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        packet.syn() && !packet.ack() && packet.seq_number() + 1 == self.0.tcb.rcv_nxt
+    }
+
+    /// Whether `packet` acknowledges something that is not part of our
+    /// handshake, the peer gets a RST for it.
+    pub fn bad_ack<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        let segment = self.0.parse_raw(packet.as_ref());
+        self.0.check(&segment) == Verdict::BadAck
+    }
+
+    /// Whether [Self::delivers_data] holds for `packet` and its ACK is
+    /// delayed, it goes out later with our data or when the timer fires.
+    pub fn ack_delayed<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        let segment = self.0.parse_raw(packet.as_ref());
+        self.delivers_data(packet) && self.0.ack_delay(&segment).is_some()
+    }
+
+    /// Whether `packet` is in order and gets accepted, with or without data.
+    pub fn acceptable<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        let segment = self.0.parse_raw(packet.as_ref());
+        self.0.check(&segment) == Verdict::InOrder
+    }
+
+    /// Whether `packet` gets accepted and carries data we have not received yet.
+    pub fn delivers_data<U>(&self, packet: &TcpPacket<U>) -> bool
+    where
+        U: AsRef<[u8]>,
+    {
+        let segment = self.0.parse_raw(packet.as_ref());
+        self.0.check(&segment) == Verdict::InOrder
+            && segment.seq_number + segment.payload.len() > self.0.tcb.rcv_nxt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iss::SeededIss;
+
+    const LOCAL: Ipv4Address = Ipv4Address([10, 0, 0, 1]);
+    const REMOTE: Ipv4Address = Ipv4Address([10, 0, 0, 2]);
+    const REMOTE_PORT: u16 = 4000;
+    const IRS: TcpSeqNumber = TcpSeqNumber(1000);
+
+    fn repr(control: TcpControl, seq: TcpSeqNumber, ack: Option<TcpSeqNumber>) -> TcpRepr<'static> {
+        TcpRepr {
+            src_port: REMOTE_PORT,
+            dst_port: 555,
+            control,
+            seq_number: seq,
+            ack_number: ack,
+            window_len: 1000,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None, None, None],
+            payload: &[],
+        }
+    }
+
+    fn emit(repr: &TcpRepr) -> TcpPacket<Vec<u8>> {
+        let mut buf = vec![0; repr.buffer_len()];
+        repr.emit(
+            &mut TcpPacket::new_unchecked(&mut buf),
+            &IpAddress::from(REMOTE),
+            &IpAddress::from(LOCAL),
+            &ChecksumCapabilities::default(),
+        );
+        TcpPacket::new_unchecked(buf)
+    }
+
+    /// Passive open of a crafted SYN, the peer's window is 1000 bytes.
+    fn syn_rcvd(config: TcpConfig) -> Tcp<SynRcvd> {
+        passive_open(config, &repr(TcpControl::Syn, IRS, None)).0
+    }
+
+    /// Passive open of the given SYN.
+    fn passive_open(config: TcpConfig, syn: &TcpRepr) -> (Tcp<SynRcvd>, SynAck) {
+        let listen = TcpClosed::with_config(config).open(
+            LocalAddr {
+                addr: LOCAL,
+                checksum_caps: ChecksumCapabilities::default(),
+                port: 555,
+                mtu: 1500,
+            },
+            None,
+        );
+        listen.recv_syn(REMOTE, &Syn::from_packet(emit(syn)))
+    }
+
+    /// The options and window of a segment we sent.
+    fn parse_sent(packet: &TcpPacket<Vec<u8>>) -> TcpRepr<'_> {
+        TcpRepr::parse(
             &TcpPacket::new_unchecked(packet.as_ref()),
-            &IpAddress::from(self.0.remote.addr),
-            &IpAddress::from(self.0.local.addr),
-            &self.0.local.checksum_caps,
+            &IpAddress::from(LOCAL),
+            &IpAddress::from(REMOTE),
+            &ChecksumCapabilities::default(),
         )
-        .unwrap();
-        self.0.accept(&packet)
+        .unwrap()
+    }
+
+    /// Active open towards the crafted peer.
+    fn syn_sent(config: TcpConfig) -> (Tcp<SynSent>, Syn) {
+        TcpClosed::with_config(config).connect(
+            LocalAddr {
+                addr: LOCAL,
+                checksum_caps: ChecksumCapabilities::default(),
+                port: 555,
+                mtu: 1500,
+            },
+            RemoteAddr {
+                addr: REMOTE,
+                port: REMOTE_PORT,
+            },
+            None,
+        )
+    }
+
+    /// [syn_rcvd] completed by a crafted ACK.
+    fn established(config: TcpConfig) -> Tcp<Established> {
+        let tcp = syn_rcvd(config);
+        let ack = emit(&repr(TcpControl::None, IRS + 1, Some(tcp.tcb.snd_nxt)));
+        tcp.recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .unwrap()
+    }
+
+    /// [established] closed by us, with the FIN sent.
+    fn fin_wait_1() -> Tcp<FinWait1> {
+        let (mut tcp, _) = established(TcpConfig::default()).close();
+        tcp.send_fin();
+        tcp
+    }
+
+    /// [fin_wait_1] with the FIN acknowledged by a crafted ACK.
+    fn fin_wait_2() -> Tcp<FinWait2> {
+        let tcp = fin_wait_1();
+        let ack = emit(&repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_nxt),
+        ));
+        tcp.recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .unwrap()
+    }
+
+    /// [established] closed by a crafted FIN.
+    fn close_wait() -> Tcp<CloseWait> {
+        let tcp = established(TcpConfig::default());
+        let fin = emit(&repr(
+            TcpControl::Fin,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_nxt),
+        ));
+        let Reaction::Acceptable(tcp, Some(_), None) = tcp.recv_fin(&FinAck::from_packet(fin))
+        else {
+            panic!("FIN not accepted");
+        };
+        tcp
+    }
+
+    /// [close_wait] closed by us as well.
+    fn last_ack() -> Tcp<LastAck> {
+        close_wait().close().0
+    }
+
+    fn accept<'a>(tcp: &mut Tcp<Established>, repr: TcpRepr<'a>) -> ReactionInner<'a> {
+        tcp.accept(&Segment {
+            repr,
+            timestamp: None,
+            urgent: None,
+        })
+    }
+
+    fn challenged(reaction: ReactionInner, tcp: &Tcp<Established>) -> bool {
+        match reaction {
+            ReactionInner::NotAcceptable(Some(ack)) => ack.packet().ack_number() == tcp.tcb.rcv_nxt,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn rst_at_rcv_nxt_resets() {
+        let mut tcp = established(TcpConfig::default());
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let reaction = accept(&mut tcp, repr(TcpControl::Rst, seq, Some(ack)));
+        assert!(matches!(reaction, ReactionInner::Reset(None)));
+    }
+
+    #[test]
+    fn fin_wait_2_ignores_duplicate_ack() {
+        let tcp = fin_wait_2();
+        let ack = emit(&repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_nxt),
+        ));
+        assert!(tcp
+            .recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .is_some());
+    }
+
+    #[test]
+    fn fin_wait_2_is_reset_only_at_rcv_nxt() {
+        let tcp = fin_wait_2();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let rst = Rst::from_packet(emit(&repr(TcpControl::Rst, seq + 100, Some(ack))));
+        let tcp = match tcp.recv_rst(&rst) {
+            Reaction::NotAcceptable(tcp, Some(_)) => tcp,
+            _ => panic!("expected a challenge ACK"),
+        };
+        let rst = Rst::from_packet(emit(&repr(TcpControl::Rst, seq, Some(ack))));
+        assert!(matches!(tcp.recv_rst(&rst), Reaction::Reset(None)));
+    }
+
+    #[test]
+    fn fin_wait_1_drops_data_along_with_the_ack_of_our_fin() {
+        let tcp = fin_wait_1();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let mut seg = repr(TcpControl::Psh, seq, Some(ack));
+        seg.payload = b"late";
+        let packet = emit(&seg);
+        let picker = tcp.for_picker();
+        assert!(picker.acceptable(&packet) && picker.acks_fin(&packet));
+
+        let Reaction::Acceptable(tcp, Some(ack), Some(_)) = tcp.recv_ack(&Ack::from_packet(packet))
+        else {
+            panic!("expected FIN-WAIT-2 and an ACK of the data");
+        };
+        assert_eq!(ack.packet().ack_number(), seq + 4);
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn fin_wait_1_drops_the_payload_of_a_fin() {
+        // the peer's FIN acknowledges ours
+        let tcp = fin_wait_1();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let mut seg = repr(TcpControl::Fin, seq, Some(ack));
+        seg.payload = b"bye";
+        let Reaction::Acceptable(tcp, Some(ack), Some(_)) =
+            tcp.recv_fin(&FinAck::from_packet(emit(&seg)))
+        else {
+            panic!("expected TIME-WAIT");
+        };
+        assert_eq!(ack.packet().ack_number(), seq + 4);
+        assert!(tcp.recv_buffer.is_empty());
+
+        // or both sides close at the same time
+        let tcp = fin_wait_1();
+        let mut seg = repr(TcpControl::Fin, seq, Some(tcp.tcb.snd_una));
+        seg.payload = b"bye";
+        let packet = emit(&seg);
+        assert!(!tcp.for_picker().acks_fin(&packet));
+        let Reaction::Acceptable(tcp, Some(ack), Some(_)) =
+            tcp.recv_simultaneous_fin(&FinAck::from_packet(packet))
+        else {
+            panic!("expected CLOSING");
+        };
+        assert_eq!(ack.packet().ack_number(), seq + 4);
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn fin_wait_1_acks_unacceptable_fins() {
+        let tcp = fin_wait_1();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        // a duplicate, and one beyond a hole
+        for seq in [seq - 1, seq + 10] {
+            let fin = emit(&repr(TcpControl::Fin, seq, Some(ack)));
+            assert!(!tcp.for_picker().acceptable(&fin));
+        }
+        let fin = emit(&repr(TcpControl::Fin, seq - 1, Some(ack)));
+        let Reaction::NotAcceptable(tcp, Some(reply)) = tcp.recv_fin(&FinAck::from_packet(fin))
+        else {
+            panic!("expected an ACK");
+        };
+        assert_eq!(reply.packet().ack_number(), seq);
+        assert_eq!(tcp.tcb.rcv_nxt, seq);
+    }
+
+    #[test]
+    fn simultaneous_close_goes_through_closing() {
+        let tcp = fin_wait_1();
+        let (seq, our_fin) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_una);
+        let fin = emit(&repr(TcpControl::Fin, seq, Some(our_fin)));
+        assert!(!tcp.for_picker().acks_fin(&fin));
+        let Reaction::Acceptable(mut tcp, Some(ack), None) =
+            tcp.recv_simultaneous_fin(&FinAck::from_packet(fin))
+        else {
+            panic!("expected CLOSING");
+        };
+        assert_eq!(ack.packet().ack_number(), seq + 1);
+
+        // the peer's FIN again, now acknowledging ours, gets the same ACK
+        let fin = emit(&repr(TcpControl::Fin, seq, Some(our_fin + 1)));
+        assert!(!tcp.for_picker().acceptable(&fin));
+        let ack = tcp.recv_fin(&FinAck::from_packet(fin));
+        assert_eq!(ack.packet().ack_number(), seq + 1);
+
+        // an old ACK of our FIN is answered but does not end CLOSING
+        let old = emit(&repr(TcpControl::None, seq - 100, Some(our_fin + 1)));
+        let picker = tcp.for_picker();
+        assert!(picker.acks_fin(&old) && !picker.acceptable(&old));
+        let segments = tcp.recv_ack_of_data(&Ack::from_packet(old));
+        assert_eq!(segments.0.len(), 1);
+        assert!(!tcp.retransmission_queue_is_empty());
+
+        // text after the peer's FIN is acknowledged and dropped
+        let mut seg = repr(TcpControl::Psh, seq + 1, Some(our_fin + 1));
+        seg.payload = b"late";
+        let packet = emit(&seg);
+        let picker = tcp.for_picker();
+        assert!(picker.acks_fin(&packet) && picker.acceptable(&packet));
+        let Reaction::Acceptable(tcp, Some(ack), None) = tcp.recv_ack(&Ack::from_packet(packet))
+        else {
+            panic!("expected TIME-WAIT");
+        };
+        assert_eq!(ack.packet().ack_number(), seq + 1);
+        assert_eq!(tcp.tcb.rcv_nxt, seq + 1);
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn close_wait_acks_a_retransmitted_fin_while_draining() {
+        let mut tcp = close_wait();
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        tcp.send(b"data");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        assert!(!tcp.is_drained());
+
+        let fin = emit(&repr(TcpControl::Fin, rcv_nxt - 1, Some(tcp.tcb.snd_una)));
+        assert!(!tcp.for_picker().resets(&fin));
+        let ack = tcp.recv_fin(&FinAck::from_packet(fin));
+        assert_eq!(ack.packet().ack_number(), rcv_nxt);
+        assert_eq!(tcp.tcb.rcv_nxt, rcv_nxt);
+        assert!(!tcp.is_drained());
+    }
+
+    #[test]
+    fn last_ack_closes_only_on_an_acceptable_ack_of_our_fin() {
+        let mut tcp = last_ack();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+
+        // it covers our FIN, but lies outside the window
+        let old = emit(&repr(TcpControl::None, seq - 100, Some(ack)));
+        let picker = tcp.for_picker();
+        assert!(picker.acks_fin(&old) && !picker.acceptable(&old));
+        let segments = tcp.recv_ack_of_data(&Ack::from_packet(old));
+        assert_eq!(segments.0.len(), 1);
+        assert_eq!(segments.0[0].packet().ack_number(), seq);
+        assert!(!tcp.retransmission_queue_is_empty());
+
+        let ack = emit(&repr(TcpControl::None, seq, Some(ack)));
+        let picker = tcp.for_picker();
+        assert!(picker.acks_fin(&ack) && picker.acceptable(&ack));
+        assert!(tcp
+            .recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .is_some());
+    }
+
+    #[test]
+    fn last_ack_acks_a_retransmitted_fin() {
+        let mut tcp = last_ack();
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        // the peer's FIN again, from before it saw ours
+        let fin = emit(&repr(TcpControl::Fin, rcv_nxt - 1, Some(tcp.tcb.snd_una)));
+        assert!(fin.fin() && !tcp.for_picker().acks_fin(&fin));
+        let ack = tcp.recv_fin(&FinAck::from_packet(fin));
+        assert_eq!(ack.packet().ack_number(), rcv_nxt);
+        assert!(!tcp.retransmission_queue_is_empty());
+    }
+
+    #[test]
+    fn time_wait_lingers_until_2_msl_after_the_last_fin() {
+        let tcp = fin_wait_2();
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let fin = emit(&repr(TcpControl::Fin, seq, Some(ack)));
+        let Reaction::Acceptable(mut tcp, Some(_), None) =
+            tcp.recv_fin(&FinAck::from_packet(fin.clone()))
+        else {
+            panic!("expected TIME-WAIT");
+        };
+        let linger = tcp.config.msl * 2;
+        assert!(tcp.timeout() > linger - Duration::from_secs(1));
+
+        // only a FIN with an ACK or a RST gets through
+        let mut no_ack = repr(TcpControl::Fin, seq, None);
+        assert!(!tcp.filter(REMOTE, &emit(&no_ack)));
+        no_ack.control = TcpControl::None;
+        no_ack.ack_number = Some(ack);
+        assert!(!tcp.filter(REMOTE, &emit(&no_ack)));
+        assert!(tcp.filter(REMOTE, &fin));
+
+        // most of it has passed, a challenged RST does not restart the timer
+        tcp.tcb.time_wait_deadline = Some(Instant::now() + Duration::from_millis(10));
+        let rst = emit(&repr(TcpControl::Rst, seq + 100, Some(ack)));
+        assert!(tcp.filter(REMOTE, &rst));
+        let Reaction::NotAcceptable(mut tcp, Some(_)) = tcp.recv_rst(&Rst::from_packet(rst)) else {
+            panic!("expected a challenge ACK");
+        };
+        assert!(tcp.timeout() <= Duration::from_millis(10));
+
+        // a retransmitted FIN is acknowledged again and does
+        let reack = tcp.recv_fin(&FinAck::from_packet(fin));
+        assert_eq!(reack.packet().ack_number(), seq + 1);
+        assert!(tcp.timeout() > linger - Duration::from_secs(1));
+
+        tcp.tcb.time_wait_deadline = Some(Instant::now() - Duration::from_millis(1));
+        assert_eq!(tcp.timeout(), Duration::ZERO);
+    }
+
+    #[test]
+    fn syn_received_answers_syns_and_listens_again_on_reset() {
+        let mut tcp = syn_rcvd(TcpConfig::default());
+        let snd_una = tcp.tcb.snd_una;
+
+        let syn = emit(&repr(TcpControl::Syn, IRS, None));
+        assert!(tcp.for_picker().retransmitted_syn(&syn));
+        let syn_ack = tcp.recv_retransmitted_syn(&Syn::from_packet(syn));
+        assert_eq!(syn_ack.packet().seq_number(), snd_una);
+        assert_eq!(syn_ack.packet().ack_number(), IRS + 1);
+
+        let syn = emit(&repr(TcpControl::Syn, IRS + 500, None));
+        assert!(!tcp.for_picker().retransmitted_syn(&syn));
+        let ack = tcp.recv_syn(&Syn::from_packet(syn)).unwrap();
+        assert_eq!(ack.packet().ack_number(), IRS + 1);
+
+        let rst = emit(&repr(TcpControl::Rst, IRS + 1, None));
+        assert!(tcp.for_picker().resets(&rst));
+        let listen = tcp.recv_reset(&Rst::from_packet(rst));
+        assert_eq!(listen.local.port, 555);
+    }
+
+    #[test]
+    fn abort_resets_at_snd_nxt() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        tcp.send(b"ab");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        let snd_nxt = tcp.tcb.snd_nxt;
+        let (_, rst) = tcp.abort();
+        assert_eq!(rst.packet().seq_number(), snd_nxt);
+        assert!(!rst.packet().ack());
+    }
+
+    #[test]
+    fn unmatched_segments_are_reset() {
+        let caps = ChecksumCapabilities::default();
+        let reset = |repr| reset_unmatched(LOCAL, REMOTE, &caps, &emit(&repr));
+
+        let rst = reset(repr(TcpControl::None, IRS, Some(TcpSeqNumber(500)))).unwrap();
+        assert_eq!(rst.packet().seq_number(), TcpSeqNumber(500));
+        assert!(!rst.packet().ack());
+
+        let rst = reset(repr(TcpControl::Syn, IRS, None)).unwrap();
+        assert_eq!(rst.packet().seq_number(), TcpSeqNumber(0));
+        assert!(rst.packet().ack());
+        assert_eq!(rst.packet().ack_number(), IRS + 1);
+        assert_eq!(rst.packet().dst_port(), REMOTE_PORT);
+
+        assert!(reset(repr(TcpControl::Rst, IRS, None)).is_none());
+    }
+
+    #[test]
+    fn rst_in_window_gets_challenge_ack() {
+        let mut tcp = established(TcpConfig::default());
+        let (seq, ack) = (tcp.tcb.rcv_nxt + 100, tcp.tcb.snd_nxt);
+        let reaction = accept(&mut tcp, repr(TcpControl::Rst, seq, Some(ack)));
+        assert!(challenged(reaction, &tcp));
+    }
+
+    #[test]
+    fn rst_outside_window_is_dropped() {
+        let mut tcp = established(TcpConfig::default());
+        let seq = tcp.tcb.rcv_nxt + tcp.tcb.rcv_wnd as usize;
+        let reaction = accept(&mut tcp, repr(TcpControl::Rst, seq, None));
+        assert!(matches!(reaction, ReactionInner::NotAcceptable(None)));
+    }
+
+    #[test]
+    fn syn_in_window_gets_challenge_ack() {
+        let mut tcp = established(TcpConfig::default());
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        let reaction = accept(&mut tcp, repr(TcpControl::Syn, rcv_nxt, None));
+        assert!(challenged(reaction, &tcp));
+        assert_eq!(tcp.tcb.rcv_nxt, rcv_nxt);
+    }
+
+    #[test]
+    fn synchronized_states_challenge_syns_and_drop_segments_without_ack() {
+        let mut tcp = established(TcpConfig::default());
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        let snd_nxt = tcp.tcb.snd_nxt;
+
+        // with or without ACK, a SYN reaches the pickers and becomes a Syn
+        for ack in [None, Some(snd_nxt)] {
+            let syn = emit(&repr(TcpControl::Syn, rcv_nxt, ack));
+            assert!(tcp.filter(REMOTE, &syn));
+            let picker = tcp.for_picker();
+            assert!(!picker.resets(&syn) && !picker.acceptable(&syn));
+            let challenge = tcp.recv_syn(&Syn::from(syn)).expect("challenge ACK");
+            assert_eq!(challenge.packet().seq_number(), snd_nxt);
+            assert_eq!(challenge.packet().ack_number(), rcv_nxt);
+        }
+        assert_eq!(tcp.tcb.rcv_nxt, rcv_nxt);
+
+        // nothing else without ACK gets that far
+        let mut fin = repr(TcpControl::Fin, rcv_nxt, None);
+        assert!(!tcp.filter(REMOTE, &emit(&fin)));
+        fin.ack_number = Some(snd_nxt);
+        assert!(tcp.filter(REMOTE, &emit(&fin)));
+        let mut data = repr(TcpControl::Psh, rcv_nxt, None);
+        data.payload = b"data";
+        assert!(!tcp.filter(REMOTE, &emit(&data)));
+        assert!(tcp.filter(REMOTE, &emit(&repr(TcpControl::Rst, rcv_nxt, None))));
+
+        // the same once both sides have closed
+        let mut tcp = last_ack();
+        let syn = emit(&repr(TcpControl::Syn, tcp.tcb.rcv_nxt, None));
+        assert!(tcp.recv_syn(&Syn::from(syn)).is_some());
+        assert!(!tcp.retransmission_queue_is_empty());
+    }
+
+    #[test]
+    fn syn_outside_window_gets_challenge_ack() {
+        let mut tcp = established(TcpConfig::default());
+        let seq = tcp.tcb.rcv_nxt - 5000;
+        let reaction = accept(&mut tcp, repr(TcpControl::Syn, seq, None));
+        assert!(challenged(reaction, &tcp));
+    }
+
+    #[test]
+    fn ack_of_unsent_data_gets_challenge_ack() {
+        let mut tcp = established(TcpConfig::default());
+        let mut seg = repr(TcpControl::None, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt + 1));
+        seg.payload = b"data";
+        let reaction = accept(&mut tcp, seg);
+        assert!(challenged(reaction, &tcp));
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn ack_older_than_max_snd_wnd_gets_challenge_ack() {
+        let mut tcp = established(TcpConfig::default());
+        assert_eq!(tcp.tcb.max_snd_wnd, 1000);
+        let mut seg = repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_una - 1001),
+        );
+        seg.payload = b"data";
+        let reaction = accept(&mut tcp, seg);
+        assert!(challenged(reaction, &tcp));
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn old_ack_within_max_snd_wnd_is_accepted() {
+        let mut tcp = established(TcpConfig::default());
+        let mut seg = repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_una - 1000),
+        );
+        seg.payload = b"data";
+        let reaction = accept(&mut tcp, seg);
+        assert!(matches!(
+            reaction,
+            ReactionInner::Acceptable(Some(_), Some(data)) if *data == *b"data"
+        ));
+    }
+
+    #[test]
+    fn segments_leave_room_for_sack_blocks_and_timestamps() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        tcp.tcb.snd_mss = 100;
+        tcp.tcb.sack_permitted = true;
+        tcp.tcb.ts_recent = Some((1, Instant::now()));
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        tcp.reassembly.insert(rcv_nxt + 10, b"x");
+        tcp.reassembly.insert(rcv_nxt + 20, b"y");
+
+        tcp.send(&[0; 150]);
+        let segments = tcp.transmit();
+        let packet = TcpPacket::new_unchecked(segments.0[0].packet().as_ref());
+        let options = usize::from(packet.header_len()) - TCP_HEADER_LEN;
+        // 12 bytes of timestamps, 2 + 2 * 8 bytes of SACK padded to 20
+        assert_eq!(options, 32);
+        assert_eq!(packet.payload().len(), 100 - 32);
+    }
+
+    fn accept_stamped<'a>(
+        tcp: &mut Tcp<Established>,
+        repr: TcpRepr<'a>,
+        val: u32,
+    ) -> ReactionInner<'a> {
+        tcp.accept(&Segment {
+            repr,
+            timestamp: Some(Timestamp { val, ecr: 0 }),
+            urgent: None,
+        })
+    }
+
+    #[test]
+    fn paws_rejects_old_timestamps() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.tcb.ts_recent = Some((100, Instant::now()));
+        let seg = repr(TcpControl::None, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+
+        let reaction = accept_stamped(&mut tcp, seg, 99);
+        assert!(matches!(reaction, ReactionInner::NotAcceptable(Some(_))));
+        assert_eq!(tcp.tcb.ts_recent.unwrap().0, 100);
+
+        // a RST is not subject to PAWS
+        let rst = repr(TcpControl::Rst, tcp.tcb.rcv_nxt, None);
+        assert!(matches!(
+            accept_stamped(&mut tcp, rst, 99),
+            ReactionInner::Reset(None)
+        ));
+    }
+
+    #[test]
+    fn paws_compares_timestamps_across_the_wraparound() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.tcb.ts_recent = Some((u32::MAX - 10, Instant::now()));
+        let seg = repr(TcpControl::None, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+
+        let reaction = accept_stamped(&mut tcp, seg, u32::MAX - 20);
+        assert!(matches!(reaction, ReactionInner::NotAcceptable(_)));
+        let reaction = accept_stamped(&mut tcp, seg, 5);
+        assert!(matches!(reaction, ReactionInner::Acceptable(None, None)));
+        assert_eq!(tcp.tcb.ts_recent.unwrap().0, 5);
+    }
+
+    #[test]
+    fn paws_ignores_ts_recent_after_a_long_idle() {
+        let mut tcp = established(TcpConfig::default());
+        let idle = PAWS_IDLE + Duration::from_secs(1);
+        tcp.tcb.ts_recent = Some((100, Instant::now() - idle));
+        let seg = repr(TcpControl::None, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+
+        let reaction = accept_stamped(&mut tcp, seg, 99);
+        assert!(matches!(reaction, ReactionInner::Acceptable(None, None)));
+        // older than TS.Recent all the same, so not recorded
+        assert_eq!(tcp.tcb.ts_recent.unwrap().0, 100);
+    }
+
+    #[test]
+    fn ts_recent_follows_in_order_segments_only() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.tcb.ts_recent = Some((100, Instant::now()));
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+
+        // out of order, beyond Last.ACK.sent
+        let mut seg = repr(TcpControl::Psh, rcv_nxt + 4, Some(tcp.tcb.snd_nxt));
+        seg.payload = b"efgh";
+        let _ = accept_stamped(&mut tcp, seg, 110);
+        assert_eq!(tcp.tcb.ts_recent.unwrap().0, 100);
+
+        // fills the hole, the ACK it gets moves Last.ACK.sent along
+        let mut seg = repr(TcpControl::Psh, rcv_nxt, Some(tcp.tcb.snd_nxt));
+        seg.payload = b"abcd";
+        let _ = accept_stamped(&mut tcp, seg, 120);
+        assert_eq!(tcp.tcb.ts_recent.unwrap().0, 120);
+        assert_eq!(tcp.tcb.last_ack_sent, rcv_nxt + 8);
+    }
+
+    #[test]
+    fn zero_window_drops_only_the_text() {
+        let config = TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        };
+        let mut tcp = established(config);
+        tcp.send(b"ab");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        tcp.tcb.rcv_wnd = 0;
+
+        let mut seg = repr(TcpControl::Psh, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+        seg.payload = b"data";
+        let reaction = accept(&mut tcp, seg);
+        let ReactionInner::NotAcceptable(Some(ack)) = reaction else {
+            panic!("expected an ACK");
+        };
+        assert_eq!(ack.packet().ack_number(), tcp.tcb.rcv_nxt);
+        assert_eq!(ack.packet().window_len(), 0);
+        // the ACK it carried is processed all the same
+        assert_eq!(tcp.tcb.snd_una, tcp.tcb.snd_nxt);
+        assert!(tcp.retransmission_queue_is_empty());
+        assert!(tcp.recv_buffer.is_empty());
+    }
+
+    #[test]
+    fn pickers_know_whether_the_ack_is_delayed() {
+        let mut tcp = established(TcpConfig {
+            delayed_ack: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+        for delayed in [true, false] {
+            let mut seg = repr(TcpControl::Psh, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+            seg.payload = b"data";
+            let packet = emit(&seg);
+            assert_eq!(tcp.for_picker().ack_delayed(&packet), delayed);
+            let Reaction::Acceptable(next, ack, Some(_)) = tcp.recv(&Ack::from_packet(packet))
+            else {
+                panic!("data not accepted");
+            };
+            assert_eq!(ack.is_none(), delayed);
+            tcp = next;
+        }
+    }
+
+    #[test]
+    fn urgent_pointer_is_reported_once() {
+        let tcp = established(TcpConfig::default());
+        let mut seg = repr(TcpControl::Psh, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+        seg.payload = b"abcdef";
+        let mut packet = emit(&seg);
+        packet.set_urg(true);
+        packet.set_urgent_at(2);
+        packet.fill_checksum(&IpAddress::from(REMOTE), &IpAddress::from(LOCAL));
+
+        let Reaction::Acceptable(mut tcp, _, _) = tcp.recv(&Ack::from_packet(packet)) else {
+            panic!("segment not accepted");
+        };
+        assert_eq!(tcp.urgent(), Some(2));
+        assert_eq!(tcp.urgent(), None);
+        assert_eq!(tcp.read(), b"abcdef");
+    }
+
+    #[test]
+    fn data_that_comes_with_the_fin_is_read_in_close_wait() {
+        let tcp = established(TcpConfig::default());
+        let rcv_nxt = tcp.tcb.rcv_nxt;
+        let mut seg = repr(TcpControl::Fin, rcv_nxt, Some(tcp.tcb.snd_nxt));
+        seg.payload = b"abcdef";
+        let mut packet = emit(&seg);
+        packet.set_urg(true);
+        packet.set_urgent_at(2);
+        packet.fill_checksum(&IpAddress::from(REMOTE), &IpAddress::from(LOCAL));
+
+        let Reaction::Acceptable(mut tcp, Some(ack), Some(_)) =
+            tcp.recv_fin(&FinAck::from_packet(packet))
+        else {
+            panic!("FIN not accepted");
+        };
+        assert_eq!(ack.packet().ack_number(), rcv_nxt + 7);
+        assert_eq!(tcp.urgent(), Some(2));
+        assert_eq!(tcp.read(), b"abcdef");
+        assert!(tcp.read().is_empty());
+    }
+
+    #[test]
+    fn urgent_data_sets_urgent_pointer() {
+        let mut tcp = established(TcpConfig::default());
+        assert_eq!(tcp.send_urgent(b"abc"), 3);
+        let segments = tcp.transmit();
+        let packet = segments.0[0].packet();
+        assert!(packet.urg());
+        assert_eq!(packet.urgent_at(), 3);
+
+        // urgent mode ends once the urgent data is acknowledged
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let reaction = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert!(matches!(reaction, ReactionInner::Acceptable(None, None)));
+        assert_eq!(tcp.tcb.snd_up, None);
+    }
+
+    #[test]
+    fn open_overrides_the_configured_congestion_control() {
+        let config = TcpConfig {
+            congestion: CongestionAlgorithm::Cubic,
+            ..Default::default()
+        };
+        let tcp = established(config.clone());
+        assert!(format!("{:?}", tcp.cc).starts_with("Cubic"));
+
+        let listen = TcpClosed::with_config(config).open(
+            LocalAddr {
+                addr: LOCAL,
+                checksum_caps: ChecksumCapabilities::default(),
+                port: 555,
+                mtu: 1500,
+            },
+            Some(CongestionAlgorithm::Reno),
+        );
+        let syn = emit(&repr(TcpControl::Syn, IRS, None));
+        let (tcp, _) = listen.recv_syn(REMOTE, &Syn::from_packet(syn));
+        assert!(format!("{:?}", tcp.cc).starts_with("Reno"));
+    }
+
+    #[test]
+    fn seeded_iss_makes_opens_reproducible() {
+        let config = || TcpConfig {
+            iss: Arc::new(SeededIss::new(42)),
+            ..Default::default()
+        };
+        let (_, first) = syn_sent(config());
+        let (_, second) = syn_sent(config());
+        let iss = first.packet().seq_number();
+        assert_eq!(second.packet().seq_number(), iss);
+
+        // the same seed gives the same ISS to a passive open, our SYN-ACK takes one
+        assert_eq!(syn_rcvd(config()).tcb.snd_nxt, iss + 1);
+    }
+
+    #[test]
+    fn active_open_reaches_established() {
+        let (tcp, syn) = syn_sent(TcpConfig::default());
+        let iss = syn.packet().seq_number();
+        assert!(syn.packet().syn() && !syn.packet().ack());
+        assert_eq!(tcp.tcb.snd_nxt, iss + 1);
+
+        // the peer does not scale its window and takes at most 100 bytes
+        let mut syn_ack = repr(TcpControl::Syn, IRS, Some(iss + 1));
+        syn_ack.max_seg_size = Some(100);
+        let Reaction::Acceptable(tcp, Some(ack), None) =
+            tcp.recv_syn_ack(&SynAck::from_packet(emit(&syn_ack)))
+        else {
+            panic!("SYN-ACK not accepted");
+        };
+        assert_eq!(ack.packet().seq_number(), iss + 1);
+        assert_eq!(ack.packet().ack_number(), IRS + 1);
+        assert_eq!(tcp.tcb.snd_mss, 100);
+        assert_eq!(tcp.tcb.snd_wnd, 1000);
+        assert_eq!(tcp.tcb.rcv_wnd_shift, 0);
+        assert!(tcp.retransmission_queue_is_empty());
+
+        let mut data = repr(TcpControl::Psh, IRS + 1, Some(iss + 1));
+        data.payload = b"hello";
+        let Reaction::Acceptable(mut tcp, _, _) = tcp.recv(&Ack::from_packet(emit(&data))) else {
+            panic!("data not accepted");
+        };
+        assert_eq!(tcp.read(), b"hello");
+
+        tcp.send(b"world");
+        let segments = tcp.transmit();
+        let packet = segments.0[0].packet();
+        assert_eq!(packet.seq_number(), iss + 1);
+        assert_eq!(packet.ack_number(), IRS + 6);
+    }
+
+    #[test]
+    fn bad_syn_ack_is_reset_without_leaving_syn_sent() {
+        let (tcp, syn) = syn_sent(TcpConfig::default());
+        let iss = syn.packet().seq_number();
+
+        let bad = emit(&repr(TcpControl::Syn, IRS, Some(iss + 100)));
+        assert!(tcp.for_picker().bad_ack(&bad));
+        let bad = SynAck::from_packet(bad);
+        let rst = tcp.reject_syn_ack(&bad);
+        assert_eq!(rst.packet().seq_number(), iss + 100);
+        assert!(!rst.packet().ack());
+        let Reaction::NotAcceptable(tcp, None) = tcp.recv_syn_ack(&bad) else {
+            panic!("expected to stay in SYN-SENT");
+        };
+
+        let syn_ack = emit(&repr(TcpControl::Syn, IRS, Some(iss + 1)));
+        assert!(!tcp.for_picker().bad_ack(&syn_ack));
+        assert!(matches!(
+            tcp.recv_syn_ack(&SynAck::from_packet(syn_ack)),
+            Reaction::Acceptable(_, Some(_), None)
+        ));
+    }
+
+    #[test]
+    fn rst_refuses_the_connection_only_if_it_acknowledges_our_syn() {
+        let (tcp, syn) = syn_sent(TcpConfig::default());
+        let iss = syn.packet().seq_number();
+
+        // without an ACK, or acknowledging something else, it is dropped
+        for ack in [None, Some(iss), Some(iss + 2)] {
+            let rst = emit(&repr(TcpControl::Rst, IRS, ack));
+            assert!(!tcp.for_picker().refused(&rst));
+        }
+        let rst = emit(&repr(TcpControl::Rst, IRS, None));
+        let Reaction::NotAcceptable(tcp, None) = tcp.recv_rst(&Rst::from_packet(rst)) else {
+            panic!("expected to stay in SYN-SENT");
+        };
+
+        let rst = emit(&repr(TcpControl::Rst, IRS, Some(iss + 1)));
+        assert!(tcp.for_picker().refused(&rst));
+        let _closed: TcpClosed = tcp.recv_reset(&Rst::from_packet(rst));
+    }
+
+    #[test]
+    fn syn_ack_is_retransmitted() {
+        let mut tcp = syn_rcvd(TcpConfig::default());
+        let syn_ack = tcp.retransmission().unwrap();
+        assert_eq!(syn_ack.packet().seq_number() + 1, tcp.tcb.snd_nxt);
+    }
+
+    #[test]
+    fn fin_is_retransmitted() {
+        let (mut tcp, segments) = established(TcpConfig::default()).close();
+        assert!(segments.0.is_empty());
+        assert!(tcp.for_picker().fin_due());
+        tcp.send_fin();
+        assert!(matches!(tcp.retransmission(), Some(Retransmission::Fin(_))));
+
+        // once acknowledged there is nothing left to retransmit
+        let ack = emit(&repr(TcpControl::None, IRS + 1, Some(tcp.tcb.snd_nxt)));
+        let tcp = tcp.recv_ack(&Ack::from_packet(ack)).empty_acceptable();
+        assert!(tcp.unwrap().retransmission_queue_is_empty());
+    }
+
+    #[test]
+    fn fin_waits_for_buffered_data_within_the_window() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.send(&[0; 1500]);
+        let (mut tcp, segments) = tcp.close();
+        let sent: usize = segments.0.iter().map(|s| s.packet().segment_len()).sum();
+        assert!(sent > 0 && sent <= 1000);
+        assert!(!tcp.for_picker().fin_due());
+
+        // the peer acknowledging everything sent is not an ACK of our FIN
+        let ack = emit(&repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_nxt),
+        ));
+        assert!(!tcp.for_picker().acks_fin(&ack));
+        let segments = tcp.recv_ack_of_data(&Ack::from_packet(ack));
+        let rest: usize = segments.0.iter().map(|s| s.packet().segment_len()).sum();
+        assert_eq!(sent + rest, 1500);
+
+        assert!(tcp.for_picker().fin_due());
+        let snd_nxt = tcp.tcb.snd_nxt;
+        let fin = tcp.send_fin();
+        assert_eq!(fin.packet().seq_number(), snd_nxt);
+        let ack = emit(&repr(
+            TcpControl::None,
+            tcp.tcb.rcv_nxt,
+            Some(tcp.tcb.snd_nxt),
+        ));
+        assert!(tcp.for_picker().acks_fin(&ack));
+    }
+
+    #[test]
+    fn retransmission_resends_only_unacknowledged_bytes() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        tcp.send(b"ab");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        tcp.send(b"cd");
+        assert_eq!(tcp.transmit().0.len(), 1);
+
+        // the peer acknowledges the first byte and sends data of its own
+        let mut seg = repr(TcpControl::Psh, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_una + 1));
+        seg.payload = b"xyz";
+        let reaction = accept(&mut tcp, seg);
+        assert!(matches!(
+            reaction,
+            ReactionInner::Acceptable(Some(_), Some(_))
+        ));
+
+        // the rest goes out in a single segment acknowledging the new data
+        let segments = tcp.retransmission();
+        assert_eq!(segments.0.len(), 1);
+        let packet = TcpPacket::new_unchecked(segments.0[0].packet().as_ref());
+        assert_eq!(packet.seq_number(), tcp.tcb.snd_una);
+        assert_eq!(packet.payload(), b"bcd");
+        assert_eq!(packet.ack_number(), tcp.tcb.rcv_nxt);
+    }
+
+    #[test]
+    fn only_acceptable_segments_answer_keepalives() {
+        let mut tcp = established(TcpConfig::default());
+        let idle_since = Instant::now() - Duration::from_secs(100);
+        tcp.tcb.last_recv = idle_since;
+        tcp.tcb.keepalive_probes = 2;
+
+        let seq = tcp.tcb.rcv_nxt + tcp.tcb.rcv_wnd as usize;
+        let ack = tcp.tcb.snd_nxt;
+        let reaction = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert!(matches!(reaction, ReactionInner::NotAcceptable(Some(_))));
+        assert_eq!(tcp.tcb.last_recv, idle_since);
+        assert_eq!(tcp.tcb.keepalive_probes, 2);
+
+        let seq = tcp.tcb.rcv_nxt;
+        let reaction = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert!(matches!(reaction, ReactionInner::Acceptable(None, None)));
+        assert!(tcp.tcb.last_recv > idle_since);
+        assert_eq!(tcp.tcb.keepalive_probes, 0);
+    }
+
+    #[test]
+    fn challenge_acks_are_rate_limited() {
+        let config = TcpConfig {
+            challenge_acks: Arc::new(ChallengeAckLimit::new(2)),
+            ..Default::default()
+        };
+        let mut tcp = established(config.clone());
+        // a second connection draws from the same budget
+        let mut other = established(config);
+
+        let seq = tcp.tcb.rcv_nxt + 100;
+        let reaction = accept(&mut tcp, repr(TcpControl::Rst, seq, None));
+        assert!(challenged(reaction, &tcp));
+        let reaction = accept(&mut tcp, repr(TcpControl::Syn, seq, None));
+        assert!(challenged(reaction, &tcp));
+
+        let seq = other.tcb.rcv_nxt + 100;
+        let reaction = accept(&mut other, repr(TcpControl::Rst, seq, None));
+        assert!(matches!(reaction, ReactionInner::NotAcceptable(None)));
+    }
+
+    #[test]
+    fn classifying_segments_charges_no_challenge_acks() {
+        let mut tcp = established(TcpConfig {
+            challenge_acks: Arc::new(ChallengeAckLimit::new(3)),
+            ..Default::default()
+        });
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt + 1);
+        let packet = emit(&repr(TcpControl::None, seq, Some(ack)));
+
+        // every segment is classified first, then received
+        for _ in 0..3 {
+            assert!(!tcp.for_picker().acceptable(&packet));
+            assert!(!tcp.for_picker().delivers_data(&packet));
+            tcp = match tcp.recv(&Ack::from_packet(packet.clone())) {
+                Reaction::NotAcceptable(tcp, Some(_)) => tcp,
+                _ => panic!("expected a challenge ACK"),
+            };
+        }
+        assert!(matches!(
+            tcp.recv(&Ack::from_packet(packet)),
+            Reaction::NotAcceptable(_, None)
+        ));
+    }
+
+    #[test]
+    fn rto_follows_rfc_6298() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!(rtt.rto, Duration::from_secs(1));
+
+        rtt.sample(Duration::from_secs(2));
+        assert_eq!(rtt.srtt, Some(Duration::from_secs(2)));
+        assert_eq!(rtt.rttvar, Duration::from_secs(1));
+        assert_eq!(rtt.rto, Duration::from_secs(6));
+
+        // RTTVAR = 3/4 * 1s + 1/4 * |2s - 1s|, SRTT = 7/8 * 2s + 1/8 * 1s
+        rtt.sample(Duration::from_secs(1));
+        assert_eq!(rtt.srtt, Some(Duration::from_millis(1875)));
+        assert_eq!(rtt.rttvar, Duration::from_secs(1));
+        assert_eq!(rtt.rto, Duration::from_millis(5875));
+
+        rtt.backoff();
+        assert_eq!(rtt.rto, Duration::from_millis(11750));
+        rtt.backoff();
+        rtt.backoff();
+        assert_eq!(rtt.rto, Duration::from_millis(47000));
+        rtt.backoff();
+        assert_eq!(rtt.rto, Duration::from_secs(60));
+
+        // short round trips are held at the one second minimum
+        let mut rtt = RttEstimator::new();
+        rtt.sample(Duration::from_millis(10));
+        assert_eq!(rtt.rto, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn retransmissions_back_off_and_are_not_timed() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.tcb.rtt = RttEstimator::new();
+        tcp.send(b"abc");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        assert!(tcp.tcb.rtt_probe.is_some());
+        assert!(tcp.retransmission_timeout().is_some());
+
+        assert_eq!(tcp.retransmission().0.len(), 1);
+        assert_eq!(tcp.rto(), Duration::from_secs(2));
+        assert!(tcp.tcb.rtt_probe.is_none());
+
+        // Karn's algorithm, the ACK of the retransmission is no RTT sample
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let reaction = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert!(matches!(reaction, ReactionInner::Acceptable(None, None)));
+        assert_eq!(tcp.tcb.rtt.srtt, None);
+        assert_eq!(tcp.rto(), Duration::from_secs(2));
+        assert_eq!(tcp.retransmission_timeout(), None);
+    }
+
+    #[test]
+    fn out_of_order_data_is_delivered_once_the_hole_is_filled() {
+        let mut tcp = established(TcpConfig::default());
+        let (rcv_nxt, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+
+        let mut seg = repr(TcpControl::Psh, rcv_nxt + 4, Some(ack));
+        seg.payload = b"efgh";
+        let ReactionInner::NotAcceptable(Some(dup)) = accept(&mut tcp, seg) else {
+            panic!("expected a duplicate ACK");
+        };
+        assert_eq!(dup.packet().ack_number(), rcv_nxt);
+        // the retransmission of held data changes nothing
+        assert!(matches!(
+            accept(&mut tcp, seg),
+            ReactionInner::NotAcceptable(Some(_))
+        ));
+        assert_eq!(tcp.reassembly.len(), 4);
+
+        // overlaps what is held
+        let mut seg = repr(TcpControl::Psh, rcv_nxt, Some(ack));
+        seg.payload = b"abcdef";
+        let ReactionInner::Acceptable(Some(ack), Some(data)) = accept(&mut tcp, seg) else {
+            panic!("expected data");
+        };
+        assert_eq!(&data[..], b"abcdefgh");
+        assert_eq!(ack.packet().ack_number(), rcv_nxt + 8);
+        assert!(tcp.reassembly.is_empty());
+    }
+
+    /// Feed `len` bytes of in-order data and return the window we ACKed with.
+    fn receive(tcp: &mut Tcp<Established>, len: usize) -> u16 {
+        let data = vec![0; len];
+        let mut seg = repr(TcpControl::Psh, tcp.tcb.rcv_nxt, Some(tcp.tcb.snd_nxt));
+        seg.payload = &data;
+        let ReactionInner::Acceptable(Some(ack), Some(_)) = accept(tcp, seg) else {
+            panic!("data not accepted");
+        };
+        ack.packet().window_len()
+    }
+
+    #[test]
+    fn receive_window_shrinks_as_the_buffer_fills() {
+        let mut tcp = established(TcpConfig {
+            recv_buffer_size: 4000,
+            ..Default::default()
+        });
+        let right_edge = tcp.tcb.rcv_nxt + tcp.tcb.rcv_wnd as usize;
+        for (len, wnd) in [(1000, 3000), (1500, 1500), (1500, 0)] {
+            assert_eq!(receive(&mut tcp, len), wnd);
+            // what we advertised is never taken back
+            assert_eq!(tcp.tcb.rcv_nxt + tcp.tcb.rcv_wnd as usize, right_edge);
+        }
+        assert_eq!(tcp.recv_buffer.len(), 4000);
+        assert!(!tcp.tcb.window_update_pending);
+    }
+
+    #[test]
+    fn reading_reopens_the_receive_window() {
+        let mut tcp = established(TcpConfig {
+            recv_buffer_size: 4000,
+            ..Default::default()
+        });
+        assert_eq!(receive(&mut tcp, 4000), 0);
+        assert_eq!(tcp.read().len(), 4000);
+        assert_eq!(tcp.tcb.rcv_wnd, 4000);
+
+        // the update goes out even with nothing to send
+        let segments = tcp.transmit();
+        assert_eq!(segments.0.len(), 1);
+        let update = segments.0[0].packet();
+        assert_eq!(update.ack_number(), tcp.tcb.rcv_nxt);
+        assert_eq!(update.window_len(), 4000);
+        assert!(!tcp.tcb.window_update_pending);
+    }
+
+    #[test]
+    fn small_window_updates_are_held_back() {
+        let mut tcp = established(TcpConfig {
+            recv_buffer_size: 4000,
+            ..Default::default()
+        });
+        let threshold = usize::from(tcp.tcb.snd_mss).min(2000);
+
+        assert_eq!(receive(&mut tcp, threshold - 1), (4001 - threshold) as u16);
+        tcp.read();
+        assert_eq!(tcp.tcb.rcv_wnd as usize, 4001 - threshold);
+        assert!(!tcp.tcb.window_update_pending);
+        assert!(tcp.transmit().0.is_empty());
+
+        // once the window can grow by the threshold, it does
+        assert_eq!(receive(&mut tcp, 1), (4000 - threshold) as u16);
+        tcp.read();
+        assert_eq!(tcp.tcb.rcv_wnd, 4000);
+        assert!(tcp.tcb.window_update_pending);
+    }
+
+    #[test]
+    fn mss_is_clamped_to_the_link() {
+        let local = |mtu| LocalAddr {
+            addr: LOCAL,
+            checksum_caps: ChecksumCapabilities::default(),
+            port: 555,
+            mtu,
+        };
+        assert_eq!(local(1500).mss(), 1460);
+        assert_eq!(local(30).mss(), 0);
+        assert_eq!(local(100_000).mss(), u16::MAX);
+
+        assert_eq!(local(1500).effective_snd_mss(None), 536);
+        assert_eq!(local(1500).effective_snd_mss(Some(1200)), 1200);
+        assert_eq!(local(1500).effective_snd_mss(Some(9000)), 1460);
+        assert_eq!(local(1500).effective_snd_mss(Some(0)), 1);
+    }
+
+    #[test]
+    fn segments_are_cut_at_the_peers_mss() {
+        let mut syn = repr(TcpControl::Syn, IRS, None);
+        syn.max_seg_size = Some(400);
+        let (tcp, syn_ack) = passive_open(
+            TcpConfig {
+                nodelay: true,
+                ..Default::default()
+            },
+            &syn,
+        );
+        assert_eq!(parse_sent(syn_ack.packet()).max_seg_size, Some(1460));
+        assert_eq!(tcp.tcb.snd_mss, 400);
+
+        let ack = emit(&repr(TcpControl::None, IRS + 1, Some(tcp.tcb.snd_nxt)));
+        let mut tcp = tcp
+            .recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .unwrap();
+        tcp.send(&[0; 1000]);
+        let lens: Vec<_> = tcp
+            .transmit()
+            .0
+            .iter()
+            .map(|s| s.packet().segment_len())
+            .collect();
+        assert_eq!(lens, vec![400, 400, 200]);
+    }
+
+    #[test]
+    fn window_scaling_is_used_only_if_both_sides_offer_it() {
+        // 256 KiB of receive buffer take a shift of 3 to advertise
+        let mut syn = repr(TcpControl::Syn, IRS, None);
+        syn.window_scale = Some(2);
+        let (tcp, syn_ack) = passive_open(TcpConfig::default(), &syn);
+        let syn_ack = parse_sent(syn_ack.packet());
+        assert_eq!(syn_ack.window_scale, Some(3));
+        assert_eq!(syn_ack.window_len, u16::MAX);
+
+        let ack = emit(&repr(TcpControl::None, IRS + 1, Some(tcp.tcb.snd_nxt)));
+        let mut tcp = tcp
+            .recv_ack(&Ack::from_packet(ack))
+            .empty_acceptable()
+            .unwrap();
+        assert_eq!(tcp.tcb.snd_wnd, 1000 << 2);
+        let ack = tcp.build_ack(&[]);
+        assert_eq!(ack.packet().window_len(), ((256 * 1024) >> 3) as u16);
+
+        let (tcp, syn_ack) = passive_open(TcpConfig::default(), &repr(TcpControl::Syn, IRS, None));
+        assert_eq!(parse_sent(syn_ack.packet()).window_scale, None);
+        assert_eq!(tcp.tcb.rcv_wnd, u32::from(u16::MAX));
+        assert_eq!(tcp.tcb.snd_wnd_shift, 0);
+    }
+
+    #[test]
+    fn peer_window_scale_is_capped() {
+        let (tcp, syn) = syn_sent(TcpConfig::default());
+        let iss = syn.packet().seq_number();
+        assert_eq!(parse_sent(syn.packet()).window_scale, Some(3));
+
+        let mut syn_ack = repr(TcpControl::Syn, IRS, Some(iss + 1));
+        syn_ack.window_scale = Some(15);
+        let Reaction::Acceptable(tcp, _, _) =
+            tcp.recv_syn_ack(&SynAck::from_packet(emit(&syn_ack)))
+        else {
+            panic!("SYN-ACK not accepted");
+        };
+        assert_eq!(tcp.tcb.snd_wnd_shift, MAX_WND_SHIFT);
+        // the window in the SYN-ACK itself is not scaled
+        assert_eq!(tcp.tcb.snd_wnd, 1000);
+        assert_eq!(tcp.tcb.rcv_wnd_shift, 3);
+    }
+
+    #[test]
+    fn fast_retransmit_and_newreno_recovery() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        tcp.tcb.snd_mss = 100;
+        tcp.send(&[0; 500]);
+        assert_eq!(tcp.transmit().0.len(), 5);
+        let (seq, snd_una) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_una);
+        let dup_ack = repr(TcpControl::None, seq, Some(snd_una));
+
+        for _ in 0..2 {
+            let _ = accept(&mut tcp, dup_ack);
+            assert!(tcp.transmit().0.is_empty());
+        }
+        // ssthresh is half the 500 bytes in flight, the window is inflated
+        // by the three segments that left the network
+        let _ = accept(&mut tcp, dup_ack);
+        assert_eq!((tcp.ssthresh(), tcp.cwnd()), (250, 250 + 300));
+        let segments = tcp.transmit();
+        assert_eq!(segments.0.len(), 1);
+        assert_eq!(segments.0[0].packet().seq_number(), snd_una);
+
+        let _ = accept(&mut tcp, dup_ack);
+        assert_eq!(tcp.cwnd(), 250 + 400);
+
+        // a partial ACK deflates the window and resends the next hole
+        let _ = accept(&mut tcp, repr(TcpControl::None, seq, Some(snd_una + 100)));
+        assert_eq!(tcp.cwnd(), 250 + 400);
+        let segments = tcp.transmit();
+        assert_eq!(segments.0.len(), 1);
+        assert_eq!(segments.0[0].packet().seq_number(), snd_una + 100);
+
+        // the full ACK ends recovery
+        let snd_nxt = tcp.tcb.snd_nxt;
+        let _ = accept(&mut tcp, repr(TcpControl::None, seq, Some(snd_nxt)));
+        assert_eq!((tcp.ssthresh(), tcp.cwnd()), (250, 250));
+        assert!(tcp.tcb.recover.is_none());
+    }
+
+    #[test]
+    fn retransmission_timeout_collapses_the_window() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        tcp.tcb.snd_mss = 100;
+        tcp.send(&[0; 500]);
+        assert_eq!(tcp.transmit().0.len(), 5);
+
+        assert_eq!(tcp.retransmission().0.len(), 1);
+        assert_eq!((tcp.ssthresh(), tcp.cwnd()), (250, 100));
+        // slow start again, the window grows by the bytes acknowledged
+        let (seq, snd_una) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_una);
+        let _ = accept(&mut tcp, repr(TcpControl::None, seq, Some(snd_una + 100)));
+        assert_eq!(tcp.cwnd(), 200);
+    }
+
+    #[test]
+    fn every_second_segment_is_acknowledged_right_away() {
+        let mut tcp = established(TcpConfig {
+            delayed_ack: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+        let (rcv_nxt, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let mut seg = repr(TcpControl::Psh, rcv_nxt, Some(ack));
+        seg.payload = b"ab";
+        assert!(matches!(
+            accept(&mut tcp, seg),
+            ReactionInner::Acceptable(None, Some(_))
+        ));
+        assert!(tcp.next_timeout().unwrap() <= Duration::from_millis(200));
+
+        let mut seg = repr(TcpControl::Psh, rcv_nxt + 2, Some(ack));
+        seg.payload = b"cd";
+        let ReactionInner::Acceptable(Some(ack), Some(_)) = accept(&mut tcp, seg) else {
+            panic!("expected an ACK");
+        };
+        assert_eq!(ack.packet().ack_number(), rcv_nxt + 4);
+        assert_eq!(tcp.next_timeout(), None);
+    }
+
+    #[test]
+    fn delayed_ack_goes_out_with_data_or_when_the_timer_fires() {
+        let mut tcp = established(TcpConfig {
+            delayed_ack: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+        let (rcv_nxt, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let mut seg = repr(TcpControl::Psh, rcv_nxt, Some(ack));
+        seg.payload = b"ab";
+        let _ = accept(&mut tcp, seg);
+        // nothing is due yet
+        assert!(tcp.timeout().0.is_empty());
+
+        tcp.send(b"xy");
+        let segments = tcp.transmit();
+        assert_eq!(segments.0[0].packet().ack_number(), rcv_nxt + 2);
+        assert!(tcp.tcb.ack_deadline.is_none());
+
+        let mut seg = repr(TcpControl::Psh, rcv_nxt + 2, Some(ack));
+        seg.payload = b"cd";
+        let _ = accept(&mut tcp, seg);
+        tcp.tcb.ack_deadline = Some(Instant::now() - Duration::from_millis(1));
+        let segments = tcp.timeout();
+        assert_eq!(segments.0.len(), 1);
+        assert_eq!(segments.0[0].packet().ack_number(), rcv_nxt + 4);
+        assert!(tcp.tcb.ack_deadline.is_none());
+    }
+
+    #[test]
+    fn nagle_holds_small_segments_while_data_is_in_flight() {
+        let mut tcp = established(TcpConfig::default());
+        let lens = |segments: Segments| -> Vec<usize> {
+            segments
+                .0
+                .iter()
+                .map(|s| s.packet().segment_len())
+                .collect()
+        };
+        tcp.send(b"a");
+        assert_eq!(lens(tcp.transmit()), vec![1]);
+        tcp.send(b"b");
+        assert!(tcp.transmit().0.is_empty());
+
+        // a full segment is never held, what is left over is
+        tcp.send(&[0; 600]);
+        assert_eq!(lens(tcp.transmit()), vec![536]);
+
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let _ = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert_eq!(lens(tcp.transmit()), vec![65]);
+    }
+
+    #[test]
+    fn nodelay_sends_small_segments_right_away() {
+        let mut tcp = established(TcpConfig::default());
+        tcp.send(b"a");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        tcp.send(b"b");
+        assert!(tcp.transmit().0.is_empty());
+
+        tcp.set_nodelay(true);
+        assert_eq!(tcp.transmit().0.len(), 1);
+        tcp.send(b"c");
+        assert_eq!(tcp.transmit().0.len(), 1);
+    }
+
+    #[test]
+    fn zero_window_is_probed_with_backoff() {
+        let mut tcp = established(TcpConfig {
+            nodelay: true,
+            ..Default::default()
+        });
+        let (seq, ack) = (tcp.tcb.rcv_nxt, tcp.tcb.snd_nxt);
+        let mut closed = repr(TcpControl::None, seq, Some(ack));
+        closed.window_len = 0;
+        let _ = accept(&mut tcp, closed);
+        assert_eq!(tcp.tcb.snd_wnd, 0);
+
+        tcp.send(b"abc");
+        assert!(tcp.transmit().0.is_empty());
+        let interval = tcp.send_timeout().unwrap();
+        assert!(interval <= tcp.rto() && interval > Duration::ZERO);
+        assert!(!tcp.for_picker().probe_due());
+
+        tcp.tcb.persist_deadline = Some(Instant::now() - Duration::from_millis(1));
+        assert!(tcp.for_picker().probe_due());
+        let probe = tcp.probe();
+        // one below SND.NXT, outside the peer's window so it answers
+        assert_eq!(probe.packet().seq_number() + 1, tcp.tcb.snd_nxt);
+        assert_eq!(probe.packet().segment_len(), 0);
+        // the next probe waits twice as long
+        assert_eq!(tcp.tcb.persist_backoff, 1);
+        assert!(tcp.send_timeout().unwrap() > tcp.rto());
+
+        let _ = accept(&mut tcp, repr(TcpControl::None, seq, Some(ack)));
+        assert_eq!(tcp.tcb.persist_deadline, None);
+        assert_eq!(tcp.tcb.persist_backoff, 0);
+        assert_eq!(tcp.transmit().0.len(), 1);
+    }
+
+    #[test]
+    fn keepalive_gives_up_after_the_last_probe() {
+        let mut tcp = established(TcpConfig {
+            keepalive: Some(Keepalive {
+                idle: Duration::from_secs(10),
+                interval: Duration::from_secs(1),
+                probes: 3,
+            }),
+            ..Default::default()
+        });
+        let timeout = tcp.next_timeout().unwrap();
+        assert!(timeout > Duration::from_secs(9) && timeout <= Duration::from_secs(10));
+        assert!(!tcp.for_picker().probe_due());
+
+        // idle for 12.5 seconds, the first probe is overdue and the next one
+        // is due a second after it
+        let idle_since = Instant::now() - Duration::from_millis(12_500);
+        tcp.tcb.last_recv = idle_since;
+        assert!(tcp.for_picker().probe_due());
+        let probe = tcp.probe();
+        assert_eq!(probe.packet().seq_number() + 1, tcp.tcb.snd_nxt);
+        assert_eq!(tcp.tcb.keepalive_probes, 1);
+        assert_eq!(
+            tcp.keepalive_deadline(),
+            Some(idle_since + Duration::from_secs(11))
+        );
+
+        let _ = tcp.probe();
+        assert!(!tcp.for_picker().peer_gone());
+        let _ = tcp.probe();
+        assert_eq!(tcp.tcb.keepalive_probes, 3);
+        assert!(!tcp.for_picker().peer_gone());
+        // the last probe gets an interval to be answered as well
+        tcp.tcb.last_recv = Instant::now() - Duration::from_secs(14);
+        assert!(tcp.for_picker().peer_gone());
+    }
+
+    #[test]
+    fn keepalive_waits_while_data_is_in_flight() {
+        let mut tcp = established(TcpConfig {
+            keepalive: Some(Keepalive::default()),
+            ..Default::default()
+        });
+        assert!(tcp.keepalive_deadline().is_some());
+        tcp.send(b"abc");
+        assert_eq!(tcp.transmit().0.len(), 1);
+        assert_eq!(tcp.keepalive_deadline(), None);
+        assert!(tcp.retransmission_timeout().is_some());
     }
 }
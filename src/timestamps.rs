@@ -0,0 +1,189 @@
+//! The TCP timestamps option, which `TcpRepr` does not know about, so it is
+//! parsed from and spliced into the raw segments here.
+//!
+//! Link: <https://datatracker.ietf.org/doc/html/rfc7323#section-3>
+
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{IpAddress, TcpOption, TcpPacket, TCP_HEADER_LEN};
+
+const KIND: u8 = 8;
+const NOP: u8 = 1;
+
+/// Bytes the option takes up in our segments, two NOPs keep TSval aligned.
+pub const OPTION_LEN: usize = 12;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Timestamp {
+    pub val: u32,
+    pub ecr: u32,
+}
+
+impl Timestamp {
+    fn to_bytes(self) -> [u8; OPTION_LEN] {
+        let mut bytes = [NOP, NOP, KIND, 10, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes[4..8].copy_from_slice(&self.val.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.ecr.to_be_bytes());
+        bytes
+    }
+}
+
+/// The timestamps option of a raw segment, if it carries one.
+pub fn parse(segment: &[u8]) -> Option<Timestamp> {
+    let mut options = TcpPacket::new_unchecked(segment).options();
+    while let Ok((rest, option)) = TcpOption::parse(options) {
+        match option {
+            TcpOption::EndOfList => break,
+            TcpOption::Unknown { kind: KIND, data } if data.len() == 8 => {
+                return Some(Timestamp {
+                    val: u32::from_be_bytes(data[..4].try_into().unwrap()),
+                    ecr: u32::from_be_bytes(data[4..].try_into().unwrap()),
+                });
+            }
+            _ => options = rest,
+        }
+    }
+    None
+}
+
+/// Insert the option in front of the other options of an emitted segment.
+pub fn insert(
+    segment: Vec<u8>,
+    timestamp: Timestamp,
+    src_addr: &IpAddress,
+    dst_addr: &IpAddress,
+    checksum_caps: &ChecksumCapabilities,
+) -> Vec<u8> {
+    let header_len = usize::from(TcpPacket::new_unchecked(&segment).header_len());
+
+    let mut buf = Vec::with_capacity(segment.len() + OPTION_LEN);
+    buf.extend_from_slice(&segment[..TCP_HEADER_LEN]);
+    buf.extend_from_slice(&timestamp.to_bytes());
+    buf.extend_from_slice(&segment[TCP_HEADER_LEN..]);
+
+    let mut packet = TcpPacket::new_unchecked(buf.as_mut_slice());
+    packet.set_header_len((header_len + OPTION_LEN) as u8);
+    fill_checksum(&mut packet, src_addr, dst_addr, checksum_caps);
+    buf
+}
+
+/// Overwrite the option put in place by [insert], e.g. when retransmitting.
+/// Segments without it are left alone.
+pub fn restamp(
+    segment: &mut [u8],
+    timestamp: Timestamp,
+    src_addr: &IpAddress,
+    dst_addr: &IpAddress,
+    checksum_caps: &ChecksumCapabilities,
+) {
+    let at = TCP_HEADER_LEN..TCP_HEADER_LEN + OPTION_LEN;
+    let header_len = usize::from(TcpPacket::new_unchecked(&*segment).header_len());
+    if header_len < at.end || segment[at.start..at.start + 4] != [NOP, NOP, KIND, 10] {
+        return;
+    }
+    segment[at].copy_from_slice(&timestamp.to_bytes());
+    fill_checksum(
+        &mut TcpPacket::new_unchecked(segment),
+        src_addr,
+        dst_addr,
+        checksum_caps,
+    );
+}
+
+fn fill_checksum(
+    packet: &mut TcpPacket<&mut [u8]>,
+    src_addr: &IpAddress,
+    dst_addr: &IpAddress,
+    checksum_caps: &ChecksumCapabilities,
+) {
+    if checksum_caps.tcp.tx() {
+        packet.fill_checksum(src_addr, dst_addr);
+    } else {
+        packet.set_checksum(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::wire::{Ipv4Address, TcpControl, TcpRepr, TcpSeqNumber};
+
+    const SRC: IpAddress = IpAddress::Ipv4(Ipv4Address([10, 0, 0, 1]));
+    const DST: IpAddress = IpAddress::Ipv4(Ipv4Address([10, 0, 0, 2]));
+
+    fn emit(max_seg_size: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        let repr = TcpRepr {
+            src_port: 555,
+            dst_port: 4000,
+            control: TcpControl::None,
+            seq_number: TcpSeqNumber(1000),
+            ack_number: Some(TcpSeqNumber(2000)),
+            window_len: 1000,
+            window_scale: None,
+            max_seg_size,
+            sack_permitted: false,
+            sack_ranges: [None, None, None],
+            payload,
+        };
+        let mut buf = vec![0; repr.buffer_len()];
+        repr.emit(
+            &mut TcpPacket::new_unchecked(&mut buf),
+            &SRC,
+            &DST,
+            &ChecksumCapabilities::default(),
+        );
+        buf
+    }
+
+    fn stamp(segment: Vec<u8>, val: u32, ecr: u32) -> Vec<u8> {
+        insert(
+            segment,
+            Timestamp { val, ecr },
+            &SRC,
+            &DST,
+            &ChecksumCapabilities::default(),
+        )
+    }
+
+    #[test]
+    fn inserted_option_is_parsed_back() {
+        let segment = emit(Some(1460), b"data");
+        assert!(parse(&segment).is_none());
+
+        let segment = stamp(segment, 0xdead_beef, 7);
+        let timestamp = parse(&segment).unwrap();
+        assert_eq!((timestamp.val, timestamp.ecr), (0xdead_beef, 7));
+
+        // the other options and the payload survive, the checksum is redone
+        let packet = TcpPacket::new_checked(&segment[..]).unwrap();
+        assert!(packet.verify_checksum(&SRC, &DST));
+        assert_eq!(packet.payload(), b"data");
+        let repr = TcpRepr::parse(&packet, &SRC, &DST, &ChecksumCapabilities::default()).unwrap();
+        assert_eq!(repr.max_seg_size, Some(1460));
+    }
+
+    #[test]
+    fn restamp_overwrites_only_our_option() {
+        let mut segment = stamp(emit(None, b"data"), 1, 2);
+        restamp(
+            &mut segment,
+            Timestamp { val: 3, ecr: 4 },
+            &SRC,
+            &DST,
+            &ChecksumCapabilities::default(),
+        );
+        let timestamp = parse(&segment).unwrap();
+        assert_eq!((timestamp.val, timestamp.ecr), (3, 4));
+        assert!(TcpPacket::new_unchecked(&segment[..]).verify_checksum(&SRC, &DST));
+
+        let plain = emit(Some(1460), b"data");
+        let mut restamped = plain.clone();
+        restamp(
+            &mut restamped,
+            Timestamp { val: 3, ecr: 4 },
+            &SRC,
+            &DST,
+            &ChecksumCapabilities::default(),
+        );
+        assert_eq!(restamped, plain);
+    }
+}